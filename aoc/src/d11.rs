@@ -1,4 +1,5 @@
-use std::collections::{HashMap, HashSet};
+use std::cmp::Ordering;
+use std::collections::{btree_map, BTreeMap, HashMap};
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct Galaxy {
@@ -6,80 +7,446 @@ pub struct Galaxy {
     col: usize,
 }
 
+// set of expanded (empty) lines along one axis of the image; it's stored as the complement of the lines
+// with galaxies, since in huge sparse images almost every line is empty
+#[derive(Debug, Clone, Default)]
+pub struct ExpandedLines {
+    // number of galaxies on each non-empty line
+    occupied: BTreeMap<usize, usize>,
+    // lines from this one on are outside of the image
+    len: usize,
+}
+
+impl ExpandedLines {
+    fn new(len: usize) -> ExpandedLines {
+        ExpandedLines { occupied: BTreeMap::new(), len }
+    }
+
+    pub fn contains(&self, line: &usize) -> bool {
+        *line < self.len && !self.occupied.contains_key(line)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).filter(|line| self.contains(line))
+    }
+
+    // number of expanded lines in `from..=to`
+    pub fn count_between(&self, from: usize, to: usize) -> usize {
+        if from >= self.len {
+            return 0;
+        }
+
+        let to = to.min(self.len - 1);
+        (to - from + 1) - self.occupied.range(from..=to).count()
+    }
+
+    fn occupy(&mut self, line: usize) {
+        *self.occupied.entry(line).or_insert(0) += 1;
+        self.len = self.len.max(line + 1);
+    }
+
+    fn vacate(&mut self, line: usize) {
+        if let btree_map::Entry::Occupied(mut entry) = self.occupied.entry(line) {
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Image {
     galaxies: Vec<Galaxy>,
-    expanded_rows: HashSet<usize>,
-    expanded_cols: HashSet<usize>,
+    expanded_rows: ExpandedLines,
+    expanded_cols: ExpandedLines,
+}
+
+impl Image {
+    pub fn galaxies(&self) -> &[Galaxy] {
+        &self.galaxies
+    }
+
+    // returns the id of the new galaxy; the image grows if the galaxy is outside of it,
+    // and all the new rows and columns in between start out as expanded
+    pub fn add_galaxy(&mut self, row: usize, col: usize) -> usize {
+        self.expanded_rows.occupy(row);
+        self.expanded_cols.occupy(col);
+        self.galaxies.push(Galaxy { row, col });
+        self.galaxies.len() - 1
+    }
+
+    // ids of the galaxies after the removed one shift down by one
+    pub fn remove_galaxy(&mut self, id: usize) -> Galaxy {
+        let galaxy = self.galaxies.remove(id);
+        self.expanded_rows.vacate(galaxy.row);
+        self.expanded_cols.vacate(galaxy.col);
+        galaxy
+    }
 }
 
 pub fn parse_input(input: &str) -> Image {
     let rows = input.trim().split("\n").collect::<Vec<_>>();
 
-    let mut expanded_rows = (0..rows.len()).into_iter().collect::<HashSet<_>>();
-    let mut expanded_cols = (0..rows[0].len()).into_iter().collect::<HashSet<_>>();
-    let mut galaxies = vec![];
+    let mut image = Image {
+        galaxies: vec![],
+        expanded_rows: ExpandedLines::new(rows.len()),
+        expanded_cols: ExpandedLines::new(rows[0].len()),
+    };
 
     for (row, cells) in rows.into_iter().enumerate() {
         for (col, ch) in cells.trim().chars().enumerate() {
-            match ch {
-                '#' => {
-                    galaxies.push(Galaxy { row, col });
-                    expanded_rows.remove(&row);
-                    expanded_cols.remove(&col);
-                }
-                _ => (),
+            if ch == '#' {
+                image.add_galaxy(row, col);
             }
         }
     }
 
-    Image { galaxies, expanded_rows, expanded_cols }
+    image
+}
+
+// parses a sparse image: one `row,col` pair per line; without explicit `(height, width)` bounds,
+// the image ends right after the last row and column with a galaxy
+pub fn parse_coordinates(input: &str, bounds: Option<(usize, usize)>) -> Image {
+    let (height, width) = bounds.unwrap_or((0, 0));
+    let mut image =
+        Image { galaxies: vec![], expanded_rows: ExpandedLines::new(height), expanded_cols: ExpandedLines::new(width) };
+
+    for line in input.trim().split("\n").map(|line| line.trim()).filter(|line| !line.is_empty()) {
+        let (row, col) = line.split_once(',').unwrap();
+        let (row, col) = (row.trim().parse().unwrap(), col.trim().parse().unwrap());
+
+        if bounds.is_some() && (row >= height || col >= width) {
+            panic!("galaxy {line:?} is out of bounds {height}x{width}");
+        }
+        image.add_galaxy(row, col);
+    }
+
+    image
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceOverflow {
+    // distance between the galaxies with these ids doesn't fit into usize
+    Pair(usize, usize),
+    // each pairwise distance fits, but their sum doesn't
+    Sum,
 }
 
-pub fn distances(image: &Image, factor: usize) -> (HashMap<(usize, usize), usize>, usize) {
-    let mut sum = 0;
+// pairwise distances keyed by galaxy ids, and their sum
+pub type Distances = (HashMap<(usize, usize), usize>, usize);
+
+pub fn distances(image: &Image, factor: usize) -> Result<Distances, DistanceOverflow> {
+    let mut sum: usize = 0;
     let mut pairwise = HashMap::new();
 
     for id1 in 0..image.galaxies.len() {
         for id2 in (id1 + 1)..image.galaxies.len() {
-            let &Galaxy { row: row1, col: col1 } = &image.galaxies[id1];
-            let &Galaxy { row: row2, col: col2 } = &image.galaxies[id2];
+            let dist = distance(image, id1, id2, factor)?;
 
-            let mut dist = row2.abs_diff(row1) + col2.abs_diff(col1);
+            sum = sum.checked_add(dist).ok_or(DistanceOverflow::Sum)?;
+            pairwise.insert((id1, id2), dist);
+        }
+    }
 
-            let walked_rows = row1.min(row2)..=row2.max(row1);
-            for expanded_row in &image.expanded_rows {
-                if walked_rows.contains(expanded_row) {
-                    dist += factor;
-                }
-            }
+    Ok((pairwise, sum))
+}
 
-            let walked_cols = col1.min(col2)..=col2.max(col1);
-            for expanded_col in &image.expanded_cols {
-                if walked_cols.contains(expanded_col) {
-                    dist += factor;
-                }
+fn distance(image: &Image, id1: usize, id2: usize, factor: usize) -> Result<usize, DistanceOverflow> {
+    let &Galaxy { row: row1, col: col1 } = &image.galaxies[id1];
+    let &Galaxy { row: row2, col: col2 } = &image.galaxies[id2];
+
+    let crossed_rows = image.expanded_rows.count_between(row1.min(row2), row2.max(row1));
+    let crossed_cols = image.expanded_cols.count_between(col1.min(col2), col2.max(col1));
+
+    // with huge expansion factors or coordinates any of those steps can wrap around in release builds,
+    // so we do them all with checked arithmetic and report which pair overflowed
+    crossed_rows
+        .checked_add(crossed_cols)
+        .and_then(|crossed| crossed.checked_mul(factor))
+        .and_then(|expansion| {
+            let steps = row2.abs_diff(row1).checked_add(col2.abs_diff(col1))?;
+            expansion.checked_add(steps)
+        })
+        .ok_or(DistanceOverflow::Pair(id1, id2))
+}
+
+pub fn p1(image: &Image) -> Distances {
+    distances(image, 1).unwrap()
+}
+
+pub fn p2(image: &Image) -> Distances {
+    distances(image, 1000000 - 1).unwrap()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clustering {
+    // cluster id of each galaxy; clusters are numbered in the order of their first galaxy
+    pub membership: Vec<usize>,
+    pub clusters: usize,
+    // total weight of the whole minimum spanning tree, not just of the edges within the threshold
+    pub mst_weight: usize,
+}
+
+struct DisjointSet {
+    parents: Vec<usize>,
+}
+
+impl DisjointSet {
+    fn new(size: usize) -> DisjointSet {
+        DisjointSet { parents: (0..size).collect() }
+    }
+
+    fn find(&mut self, id: usize) -> usize {
+        let mut root = id;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+
+        // path compression: point everything we walked through directly to the root
+        let mut id = id;
+        while self.parents[id] != root {
+            id = std::mem::replace(&mut self.parents[id], root);
+        }
+
+        root
+    }
+
+    fn union(&mut self, id1: usize, id2: usize) -> bool {
+        let (root1, root2) = (self.find(id1), self.find(id2));
+        self.parents[root1] = root2;
+        root1 != root2
+    }
+}
+
+// MST edges as (id1, id2, distance), sorted by distance, and the total weight of the tree
+pub type SpanningTree = (Vec<(usize, usize, usize)>, usize);
+
+// Kruskal's algorithm over expanded distances
+pub fn minimum_spanning_tree(image: &Image, factor: usize) -> Result<SpanningTree, DistanceOverflow> {
+    let mut edges = vec![];
+    for id1 in 0..image.galaxies.len() {
+        for id2 in (id1 + 1)..image.galaxies.len() {
+            edges.push((id1, id2, distance(image, id1, id2, factor)?));
+        }
+    }
+    edges.sort_by_key(|&(id1, id2, dist)| (dist, id1, id2));
+
+    let mut components = DisjointSet::new(image.galaxies.len());
+    let mut tree = vec![];
+    let mut weight: usize = 0;
+
+    for (id1, id2, dist) in edges {
+        if components.union(id1, id2) {
+            weight = weight.checked_add(dist).ok_or(DistanceOverflow::Sum)?;
+            tree.push((id1, id2, dist));
+        }
+    }
+
+    Ok((tree, weight))
+}
+
+// single-linkage clustering: galaxies end up in the same cluster if there's a chain of galaxies
+// between them with each step being at most `threshold` long, which is the same as
+// cutting all MST edges longer than `threshold`
+pub fn clusters(image: &Image, factor: usize, threshold: usize) -> Result<Clustering, DistanceOverflow> {
+    let (tree, mst_weight) = minimum_spanning_tree(image, factor)?;
+
+    let mut components = DisjointSet::new(image.galaxies.len());
+    for (id1, id2, _dist) in tree.into_iter().take_while(|&(_, _, dist)| dist <= threshold) {
+        components.union(id1, id2);
+    }
+
+    let mut cluster_ids = HashMap::new();
+    let membership = (0..image.galaxies.len())
+        .map(|id| {
+            let next_cluster_id = cluster_ids.len();
+            *cluster_ids.entry(components.find(id)).or_insert(next_cluster_id)
+        })
+        .collect();
+
+    Ok(Clustering { membership, clusters: cluster_ids.len(), mst_weight })
+}
+
+// keeps the sum of pairwise distances up to date while galaxies are added and removed,
+// without recomputing all the pairs from scratch
+#[derive(Debug, Clone)]
+pub struct DistanceTracker {
+    image: Image,
+    factor: usize,
+    sum: usize,
+}
+
+impl DistanceTracker {
+    pub fn new(image: Image, factor: usize) -> Result<DistanceTracker, DistanceOverflow> {
+        let (_pairwise, sum) = distances(&image, factor)?;
+        Ok(DistanceTracker { image, factor, sum })
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn sum(&self) -> usize {
+        self.sum
+    }
+
+    pub fn add_galaxy(&mut self, row: usize, col: usize) -> Result<usize, DistanceOverflow> {
+        // if the new galaxy lands on an expanded row, that row stops being expanded,
+        // and each pair of galaxies on different sides of it gets `factor` closer; same for columns
+        let mut sum = self.sum;
+        if self.image.expanded_rows.contains(&row) {
+            sum -= self.factor * self.straddling_pairs(|galaxy| galaxy.row.cmp(&row));
+        }
+        if self.image.expanded_cols.contains(&col) {
+            sum -= self.factor * self.straddling_pairs(|galaxy| galaxy.col.cmp(&col));
+        }
+
+        let id = self.image.add_galaxy(row, col);
+        let new_sum = (0..id).try_fold(sum, |sum, other_id| {
+            sum.checked_add(distance(&self.image, other_id, id, self.factor)?).ok_or(DistanceOverflow::Sum)
+        });
+
+        // on overflow, take the galaxy out again so that the tracker stays as it was
+        match new_sum {
+            Ok(sum) => {
+                self.sum = sum;
+                Ok(id)
+            }
+            Err(overflow) => {
+                self.image.remove_galaxy(id);
+                Err(overflow)
             }
+        }
+    }
 
-            sum += dist;
-            pairwise.insert((id1, id2), dist);
+    pub fn remove_galaxy(&mut self, id: usize) -> Result<Galaxy, DistanceOverflow> {
+        let mut sum = self.sum;
+        for other_id in (0..self.image.galaxies.len()).filter(|&other_id| other_id != id) {
+            sum -= distance(&self.image, other_id, id, self.factor)?;
+        }
+
+        let galaxy = self.image.remove_galaxy(id);
+
+        // the reverse of `add_galaxy`: emptied row or column becomes expanded and pushes apart
+        // galaxies on its different sides
+        if self.image.expanded_rows.contains(&galaxy.row) {
+            let pairs = self.straddling_pairs(|other| other.row.cmp(&galaxy.row));
+            sum =
+                pairs.checked_mul(self.factor).and_then(|delta| sum.checked_add(delta)).ok_or(DistanceOverflow::Sum)?;
+        }
+        if self.image.expanded_cols.contains(&galaxy.col) {
+            let pairs = self.straddling_pairs(|other| other.col.cmp(&galaxy.col));
+            sum =
+                pairs.checked_mul(self.factor).and_then(|delta| sum.checked_add(delta)).ok_or(DistanceOverflow::Sum)?;
         }
+
+        self.sum = sum;
+        Ok(galaxy)
     }
 
-    (pairwise, sum)
+    // number of pairs of galaxies lying on the opposite sides of some line
+    fn straddling_pairs(&self, side: impl Fn(&Galaxy) -> Ordering) -> usize {
+        let before = self.image.galaxies.iter().filter(|galaxy| side(galaxy) == Ordering::Less).count();
+        let after = self.image.galaxies.iter().filter(|galaxy| side(galaxy) == Ordering::Greater).count();
+        before * after
+    }
 }
 
-pub fn p1(image: &Image) -> (HashMap<(usize, usize), usize>, usize) {
-    distances(image, 1)
+// N-dimensional version of the image: galaxies are points with N coordinates,
+// and each axis expands along the empty slabs orthogonal to it
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct Point<const N: usize>(pub [usize; N]);
+
+#[derive(Debug, Clone)]
+pub struct Universe<const N: usize> {
+    galaxies: Vec<Point<N>>,
+    expanded: [ExpandedLines; N],
 }
 
-pub fn p2(image: &Image) -> (HashMap<(usize, usize), usize>, usize) {
-    distances(image, 1000000 - 1)
+impl<const N: usize> Universe<N> {
+    pub fn new(bounds: [usize; N]) -> Universe<N> {
+        Universe { galaxies: vec![], expanded: bounds.map(ExpandedLines::new) }
+    }
+
+    pub fn galaxies(&self) -> &[Point<N>] {
+        &self.galaxies
+    }
+
+    pub fn expanded(&self, axis: usize) -> &ExpandedLines {
+        &self.expanded[axis]
+    }
+
+    pub fn add_galaxy(&mut self, point: Point<N>) -> usize {
+        for (axis, &coord) in point.0.iter().enumerate() {
+            self.expanded[axis].occupy(coord);
+        }
+
+        self.galaxies.push(point);
+        self.galaxies.len() - 1
+    }
+}
+
+impl From<&Image> for Universe<2> {
+    fn from(image: &Image) -> Universe<2> {
+        let mut universe = Universe::new([image.expanded_rows.len, image.expanded_cols.len]);
+        for &Galaxy { row, col } in &image.galaxies {
+            universe.add_galaxy(Point([row, col]));
+        }
+        universe
+    }
+}
+
+// parses 3D images: 2D grids, same as in `parse_input`, separated by blank lines; points are (layer, row, col)
+pub fn parse_layers(input: &str) -> Universe<3> {
+    let layers = input.trim().split("\n\n").collect::<Vec<_>>();
+    let first_layer_rows = layers[0].split("\n").collect::<Vec<_>>();
+    let mut universe = Universe::new([layers.len(), first_layer_rows.len(), first_layer_rows[0].trim().len()]);
+
+    for (layer, cells) in layers.into_iter().enumerate() {
+        for (row, cells) in cells.split("\n").enumerate() {
+            for (col, ch) in cells.trim().chars().enumerate() {
+                if ch == '#' {
+                    universe.add_galaxy(Point([layer, row, col]));
+                }
+            }
+        }
+    }
+
+    universe
+}
+
+pub fn distances_nd<const N: usize>(universe: &Universe<N>, factor: usize) -> Result<Distances, DistanceOverflow> {
+    let mut sum: usize = 0;
+    let mut pairwise = HashMap::new();
+
+    for id1 in 0..universe.galaxies.len() {
+        for id2 in (id1 + 1)..universe.galaxies.len() {
+            let (Point(coords1), Point(coords2)) = (universe.galaxies[id1], universe.galaxies[id2]);
+
+            let mut dist: usize = 0;
+            for axis in 0..N {
+                let (from, to) = (coords1[axis].min(coords2[axis]), coords1[axis].max(coords2[axis]));
+                dist = universe.expanded[axis]
+                    .count_between(from, to)
+                    .checked_mul(factor)
+                    .and_then(|expansion| expansion.checked_add(to - from))
+                    .and_then(|axis_dist| dist.checked_add(axis_dist))
+                    .ok_or(DistanceOverflow::Pair(id1, id2))?;
+            }
+
+            sum = sum.checked_add(dist).ok_or(DistanceOverflow::Sum)?;
+            pairwise.insert((id1, id2), dist);
+        }
+    }
+
+    Ok((pairwise, sum))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashSet;
     use std::fs;
 
     static TEST_INPUT: &str = "...#......
@@ -102,8 +469,11 @@ mod tests {
         assert_eq!(&test_image.galaxies[5], &Galaxy { row: 6, col: 9 });
         assert_eq!(&test_image.galaxies[8], &Galaxy { row: 9, col: 4 });
 
-        assert_eq!(test_image.expanded_rows, [3, 7].into_iter().collect::<HashSet<_>>());
-        assert_eq!(test_image.expanded_cols, [2, 5, 8].into_iter().collect::<HashSet<_>>());
+        assert_eq!(test_image.expanded_rows.iter().collect::<HashSet<_>>(), [3, 7].into_iter().collect::<HashSet<_>>());
+        assert_eq!(
+            test_image.expanded_cols.iter().collect::<HashSet<_>>(),
+            [2, 5, 8].into_iter().collect::<HashSet<_>>()
+        );
     }
 
     #[test]
@@ -119,10 +489,10 @@ mod tests {
         assert_eq!(pairwise.get(&(0, 2)), Some(&6));
         assert_eq!(sum, 374);
 
-        let (_pairwise, sum) = distances(&test_image, 9);
+        let (_pairwise, sum) = distances(&test_image, 9).unwrap();
         assert_eq!(sum, 1030);
 
-        let (_pairwise, sum) = distances(&test_image, 99);
+        let (_pairwise, sum) = distances(&test_image, 99).unwrap();
         assert_eq!(sum, 8410);
 
         let image = parse_input(&fs::read_to_string("../inputs/d11").unwrap());
@@ -133,14 +503,165 @@ mod tests {
     #[test]
     fn p2_test() {
         let test_image = parse_input(TEST_INPUT);
-        let (_pairwise, sum) = distances(&test_image, 9);
+        let (_pairwise, sum) = distances(&test_image, 9).unwrap();
         assert_eq!(sum, 1030);
 
-        let (_pairwise, sum) = distances(&test_image, 99);
+        let (_pairwise, sum) = distances(&test_image, 99).unwrap();
         assert_eq!(sum, 8410);
 
         let image = parse_input(&fs::read_to_string("../inputs/d11").unwrap());
         let (_pairwise, sum) = p2(&image);
         assert_eq!(sum, 363293506944);
     }
+
+    #[test]
+    fn distances_overflow_test() {
+        let test_image = parse_input(TEST_INPUT);
+        let n = test_image.galaxies.len();
+
+        // in the test image, the sum of distances is 292 + 82 * factor, so this factor makes every
+        // pairwise distance fit, but the sum overflows
+        let factor = usize::MAX / (n * n);
+        assert_eq!(distances(&test_image, factor), Err(DistanceOverflow::Sum));
+
+        let factor = usize::MAX / 100;
+        let (_pairwise, sum) = distances(&test_image, factor).unwrap();
+        assert_eq!(sum as u128, 292 + 82 * factor as u128);
+
+        // the only pair here crosses an expanded row and an expanded column
+        let diagonal_image = parse_input("#..\n...\n..#\n");
+        assert_eq!(distances(&diagonal_image, usize::MAX / 2), Err(DistanceOverflow::Pair(0, 1)));
+
+        // the steps between the galaxies overflow on their own, even without any expansion
+        let far_image = parse_coordinates("0,0\n9223372036854775808,9223372036854775808\n", None);
+        assert_eq!(distances(&far_image, 1), Err(DistanceOverflow::Pair(0, 1)));
+    }
+
+    #[test]
+    fn clusters_test() {
+        let test_image = parse_input(TEST_INPUT);
+
+        let (tree, weight) = minimum_spanning_tree(&test_image, 1).unwrap();
+        assert_eq!(tree.len(), test_image.galaxies.len() - 1);
+        assert_eq!(tree.iter().map(|&(_, _, dist)| dist).sum::<usize>(), weight);
+        assert_eq!(weight, 44);
+
+        let clustering = clusters(&test_image, 1, 0).unwrap();
+        assert_eq!(clustering.clusters, 9);
+        assert_eq!(clustering.membership, (0..9).collect::<Vec<_>>());
+        assert_eq!(clustering.mst_weight, weight);
+
+        let clustering = clusters(&test_image, 1, usize::MAX).unwrap();
+        assert_eq!(clustering.clusters, 1);
+        assert_eq!(clustering.membership, vec![0; 9]);
+
+        let clustering = clusters(&test_image, 1, 5).unwrap();
+        assert_eq!(clustering.clusters, 5);
+        assert_eq!(clustering.membership, vec![0, 1, 2, 1, 2, 3, 4, 4, 4]);
+    }
+
+    #[test]
+    fn distance_tracker_test() {
+        let mut tracker = DistanceTracker::new(parse_input(TEST_INPUT), 9).unwrap();
+        assert_eq!(tracker.sum(), 1030);
+
+        // every change should give the same sum as recomputing all distances from scratch
+        let check = |tracker: &DistanceTracker| {
+            assert_eq!(distances(tracker.image(), 9).unwrap().1, tracker.sum());
+        };
+
+        // fills the expanded row 3 and column 5
+        assert_eq!(tracker.add_galaxy(3, 5).unwrap(), 9);
+        assert!(!tracker.image().expanded_rows.contains(&3));
+        assert!(!tracker.image().expanded_cols.contains(&5));
+        check(&tracker);
+
+        // outside of the original image
+        assert_eq!(tracker.add_galaxy(13, 2).unwrap(), 10);
+        assert_eq!(tracker.image().expanded_rows.iter().collect::<Vec<_>>(), vec![7, 10, 11, 12]);
+        check(&tracker);
+
+        // the only galaxy in row 0, so it becomes expanded again
+        assert_eq!(tracker.remove_galaxy(0).unwrap(), Galaxy { row: 0, col: 3 });
+        assert!(tracker.image().expanded_rows.contains(&0));
+        assert!(tracker.image().expanded_cols.contains(&3));
+        check(&tracker);
+
+        // galaxy (3, 5) now has id 8
+        tracker.remove_galaxy(8).unwrap();
+        check(&tracker);
+
+        while !tracker.image().galaxies().is_empty() {
+            tracker.remove_galaxy(0).unwrap();
+            check(&tracker);
+        }
+        assert_eq!(tracker.sum(), 0);
+
+        // a failed insert leaves the tracker as it was
+        let mut tracker = DistanceTracker::new(parse_input("#...\n....\n"), usize::MAX / 2).unwrap();
+        assert!(tracker.add_galaxy(0, 3).is_err());
+        assert_eq!(tracker.image().galaxies(), &[Galaxy { row: 0, col: 0 }]);
+        assert!(tracker.image().expanded_cols.contains(&3));
+        assert_eq!(tracker.sum(), 0);
+        assert_eq!(tracker.add_galaxy(1, 0).unwrap(), 1);
+        assert_eq!(tracker.sum(), 1);
+    }
+
+    #[test]
+    fn parse_coordinates_test() {
+        let test_image = parse_input(TEST_INPUT);
+        let coordinates =
+            test_image.galaxies.iter().map(|Galaxy { row, col }| format!("{row},{col}")).collect::<Vec<_>>().join("\n");
+
+        let image = parse_coordinates(&coordinates, Some((10, 10)));
+        assert_eq!(image.galaxies, test_image.galaxies);
+        assert_eq!(image.expanded_rows.iter().collect::<Vec<_>>(), vec![3, 7]);
+        assert_eq!(image.expanded_cols.iter().collect::<Vec<_>>(), vec![2, 5, 8]);
+        assert_eq!(distances(&image, 1).unwrap().1, 374);
+
+        // row 9 and column 9 have galaxies, so the implicit bounds are the same
+        let image = parse_coordinates(&coordinates, None);
+        assert_eq!(distances(&image, 1).unwrap().1, 374);
+
+        // a couple of galaxies in a huge, almost empty universe
+        let image = parse_coordinates("0,0\n1000000000000,2000000000000\n", None);
+        assert_eq!(image.expanded_rows.count_between(0, usize::MAX), 1000000000000 - 1);
+        assert_eq!(distances(&image, 1).unwrap().1, 2 * (3000000000000 - 2) + 2);
+    }
+
+    #[test]
+    fn distances_nd_test() {
+        let test_image = parse_input(TEST_INPUT);
+        let universe = Universe::from(&test_image);
+        for factor in [1, 9, 99] {
+            assert_eq!(distances_nd(&universe, factor), distances(&test_image, factor));
+        }
+
+        let universe = parse_layers(
+            "#..
+...
+..#
+
+...
+...
+...
+
+.#.
+...
+#..
+",
+        );
+        assert_eq!(universe.galaxies(), &[Point([0, 0, 0]), Point([0, 2, 2]), Point([2, 0, 1]), Point([2, 2, 0])]);
+        assert_eq!(universe.expanded(0).iter().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(universe.expanded(1).iter().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(universe.expanded(2).iter().collect::<Vec<_>>(), Vec::<usize>::new());
+
+        let (pairwise, sum) = distances_nd(&universe, 1).unwrap();
+        // 2 layers + 1 expanded layer, 2 rows + 1 expanded row, 1 column
+        assert_eq!(pairwise.get(&(1, 2)), Some(&7));
+        assert_eq!(sum, 31);
+
+        let (_pairwise, sum) = distances_nd(&universe, 9).unwrap();
+        assert_eq!(sum, 95);
+    }
 }
@@ -1,140 +1,648 @@
-use std::collections::HashMap;
+use std::fmt;
+
+use Reflection::*;
+use Symmetry::*;
+
+// a row or a column of a pattern packed into 64-bit words, with `#` as 1 and the first cell in the lowest bit;
+// unlike a single integer, this works for any pattern size, and equality is still a comparison of a few words
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Bits {
+    words: Vec<u64>,
+}
+
+impl Bits {
+    fn zeros(len: usize) -> Bits {
+        Bits { words: vec![0; len.div_ceil(64)] }
+    }
+
+    pub fn get(&self, idx: usize) -> bool {
+        self.words[idx / 64] & (1 << (idx % 64)) != 0
+    }
+
+    pub fn set(&mut self, idx: usize) {
+        self.words[idx / 64] |= 1 << (idx % 64);
+    }
+
+    pub fn flip(&mut self, idx: usize) {
+        self.words[idx / 64] ^= 1 << (idx % 64);
+    }
+
+    // number of differing bits
+    pub fn diff(&self, other: &Bits) -> usize {
+        self.words.iter().zip(&other.words).map(|(word, other_word)| (word ^ other_word).count_ones() as usize).sum()
+    }
+
+    // bits `start..(start + len)`, shifted to start at 0
+    pub fn slice(&self, start: usize, len: usize) -> Bits {
+        let (skip_words, shift) = (start / 64, start % 64);
+
+        let mut words = vec![0; len.div_ceil(64)];
+        for (idx, word) in words.iter_mut().enumerate() {
+            let low = self.words.get(skip_words + idx).copied().unwrap_or(0) >> shift;
+            let high =
+                if shift == 0 { 0 } else { self.words.get(skip_words + idx + 1).copied().unwrap_or(0) << (64 - shift) };
+            *word = low | high;
+        }
+
+        if !len.is_multiple_of(64) {
+            words[len / 64] &= (1 << (len % 64)) - 1;
+        }
+        Bits { words }
+    }
+
+    // the first `len` bits in reverse order
+    pub fn reversed(&self, len: usize) -> Bits {
+        // reversing all the words and their order moves bit `idx` to `64 * words.len() - idx - 1`
+        let reversed = Bits { words: self.words.iter().rev().map(|word| word.reverse_bits()).collect() };
+        reversed.slice(64 * self.words.len() - len, len)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Pattern {
-    rows: Vec<u32>,
-    cols: Vec<u32>,
+    rows: Vec<Bits>,
+    cols: Vec<Bits>,
+    // index of the pattern in the input, and the (1-indexed) input line it starts on
+    index: usize,
+    line: usize,
 }
 
-pub fn parse_input(input: &str) -> Vec<Pattern> {
-    let mut patterns = vec![];
+impl Pattern {
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
 
-    for pattern in input.trim().split("\n\n") {
-        let mut rows = vec![];
-        let mut char_cols: HashMap<usize, Vec<_>> = HashMap::new();
+    pub fn width(&self) -> usize {
+        self.cols.len()
+    }
 
-        for row in pattern.split("\n") {
-            let mut row_binary = vec![];
+    // true for `#`
+    pub fn cell(&self, row: usize, col: usize) -> bool {
+        self.rows[row].get(col)
+    }
+}
 
-            for (col, ch) in row.chars().enumerate() {
-                let ch = match ch {
-                    '.' => "0",
-                    '#' => "1",
-                    _ => panic!("unknown character: {ch:#?}"),
-                };
+// reflection lines are 1-indexed, as in the task: `Horizontal(n)` has n rows above it,
+// and `Vertical(n)` has n columns to the left of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reflection {
+    Horizontal(usize),
+    Vertical(usize),
+}
 
-                row_binary.push(ch);
-                char_cols.entry(col).and_modify(|char_col| char_col.push(ch)).or_insert(vec![ch]);
+impl Reflection {
+    pub fn summary(&self) -> usize {
+        match self {
+            Horizontal(row) => 100 * row,
+            Vertical(col) => *col,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoReflection {
+    pub index: usize,
+    pub line: usize,
+}
+
+impl fmt::Display for NoReflection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pattern #{} starting at line {} has no reflection", self.index, self.line)
+    }
+}
+
+pub fn parse_input(input: &str) -> Vec<Pattern> {
+    parse_patterns(input).collect()
+}
+
+// parses the patterns lazily, one block at a time, so that huge inputs don't have to be kept parsed in memory
+pub fn parse_patterns(input: &str) -> impl Iterator<Item = Pattern> + '_ {
+    blocks(input).map(|(index, line, block)| parse_pattern(block, index, line))
+}
+
+// `\n\n`-separated blocks of the input with their indexes and the (1-indexed) lines they start on
+fn blocks(input: &str) -> impl Iterator<Item = (usize, usize, &str)> {
+    // skipped blank lines at the start of the input still count for the pattern positions
+    let first_line = 1 + input[..input.len() - input.trim_start().len()].matches('\n').count();
+
+    input.trim().split("\n\n").enumerate().scan(first_line, |line, (index, block)| {
+        let block_line = *line;
+        // the block's rows and the blank line after it
+        *line += block.split('\n').count() + 1;
+        Some((index, block_line, block))
+    })
+}
+
+// reads each byte once, setting the bits of its row and column at the same time
+fn parse_pattern(block: &str, index: usize, line: usize) -> Pattern {
+    let lines = block.split('\n').map(|row| row.as_bytes()).collect::<Vec<_>>();
+    let (height, width) = (lines.len(), lines.iter().map(|row| row.len()).max().unwrap());
+
+    let mut rows = vec![Bits::zeros(width); height];
+    let mut cols = vec![Bits::zeros(height); width];
+
+    for (row, cells) in lines.into_iter().enumerate() {
+        for (col, &ch) in cells.iter().enumerate() {
+            match ch {
+                b'.' => (),
+                b'#' => {
+                    rows[row].set(col);
+                    cols[col].set(row);
+                }
+                _ => panic!("unknown character: {:#?}", ch as char),
             }
+        }
+    }
+
+    Pattern { rows, cols, index, line }
+}
+
+// number of lines mirrored on each side of the line between `line` and `line + 1`
+fn mirror_half_size(len: usize, line: usize) -> usize {
+    (line + 1).min(len - line - 1)
+}
+
+// a row or a column of a pattern, in any representation
+trait Cells {
+    // number of cells that differ from `other`'s
+    fn defects(&self, other: &Self) -> usize;
+}
+
+impl Cells for Bits {
+    fn defects(&self, other: &Bits) -> usize {
+        self.diff(other)
+    }
+}
+
+impl Cells for Vec<u8> {
+    fn defects(&self, other: &Vec<u8>) -> usize {
+        self.iter().zip(other).filter(|(symbol, other_symbol)| symbol != other_symbol).count()
+    }
+}
 
-            rows.push(u32::from_str_radix(&row_binary.join(""), 2).unwrap());
+// number of differing cells between the lines mirrored around the line between `line` and `line + 1`;
+// stops counting as soon as it's over `max_defects`
+fn mirror_defects<L: Cells>(lines: &[L], line: usize, max_defects: usize) -> usize {
+    let mut defects = 0;
+    for offset in 0..mirror_half_size(lines.len(), line) {
+        defects += lines[line - offset].defects(&lines[line + offset + 1]);
+        if defects > max_defects {
+            break;
         }
+    }
 
-        let cols = (0..(*char_cols.keys().max().unwrap() + 1))
-            .into_iter()
-            .map(|col| u32::from_str_radix(&char_cols[&col].join(""), 2).unwrap())
-            .collect();
-        patterns.push(Pattern { rows, cols });
+    defects
+}
+
+// all reflections that become perfect after fixing exactly `k` cells (smudges);
+// part 1 is `k = 0`, and part 2 is `k = 1`
+pub fn find_reflection_with_defects(pattern: &Pattern, k: usize) -> Vec<Reflection> {
+    reflections_with_defects(&pattern.rows, &pattern.cols, k)
+}
+
+fn reflections_with_defects<L: Cells>(rows: &[L], cols: &[L], k: usize) -> Vec<Reflection> {
+    let mut reflections = vec![];
+
+    // task uses 1-indexes, so the reflection between rows 0 and 1 is 1
+    for row in 0..(rows.len() - 1) {
+        if mirror_defects(rows, row, k) == k {
+            reflections.push(Horizontal(row + 1));
+        }
+    }
+
+    for col in 0..(cols.len() - 1) {
+        if mirror_defects(cols, col, k) == k {
+            reflections.push(Vertical(col + 1));
+        }
     }
 
+    reflections
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReflectionLine {
+    pub symmetry: Reflection,
+    // number of rows or columns mirrored on each side of the line
+    pub covered: usize,
+}
+
+// every perfect reflection of the pattern, rows first; `find_symmetry` only returns the first of those,
+// which hides the ambiguous patterns
+pub fn find_all_reflections(pattern: &Pattern) -> Vec<ReflectionLine> {
+    find_reflection_with_defects(pattern, 0)
+        .into_iter()
+        .map(|symmetry| {
+            let covered = match symmetry {
+                Horizontal(row) => mirror_half_size(pattern.rows.len(), row - 1),
+                Vertical(col) => mirror_half_size(pattern.cols.len(), col - 1),
+            };
+            ReflectionLine { symmetry, covered }
+        })
+        .collect()
+}
+
+fn find_symmetry(pattern: &Pattern, prev_answer: Option<Reflection>) -> Option<Reflection> {
+    find_reflection_with_defects(pattern, 0).into_iter().find(|&candidate| Some(candidate) != prev_answer)
+}
+
+// a smudge is a single defect on the mirror line, and a line with no defects is the old reflection,
+// so there's no need to flip every cell and look for a new symmetry;
+// returns the reflection and the (row, col) of the smudge, out of the two mirrored cells it's the top or the left one
+fn find_unsmudged_symmetry(pattern: &Pattern) -> Option<(Reflection, (usize, usize))> {
+    let reflection = find_reflection_with_defects(pattern, 1).into_iter().next()?;
+
+    let (lines, line) = match reflection {
+        Horizontal(row) => (&pattern.rows, row - 1),
+        Vertical(col) => (&pattern.cols, col - 1),
+    };
+    let offset = (0..mirror_half_size(lines.len(), line))
+        .find(|offset| lines[line - offset] != lines[line + offset + 1])
+        .unwrap();
+    let (smudged, mirrored) = (&lines[line - offset], &lines[line + offset + 1]);
+    let cell = (0..pattern.height().max(pattern.width())).find(|&idx| smudged.get(idx) != mirrored.get(idx)).unwrap();
+
+    let smudge = match reflection {
+        Horizontal(_) => (line - offset, cell),
+        Vertical(_) => (cell, line - offset),
+    };
+    Some((reflection, smudge))
+}
+
+// the first reflection of each pattern with exactly `smudges` defects
+pub fn find_reflections(patterns: &[Pattern], smudges: usize) -> Vec<Result<Reflection, NoReflection>> {
     patterns
+        .iter()
+        .map(|pattern| {
+            find_reflection_with_defects(pattern, smudges)
+                .into_iter()
+                .next()
+                .ok_or(NoReflection { index: pattern.index, line: pattern.line })
+        })
+        .collect()
+}
+
+pub fn p1(patterns: &[Pattern]) -> Result<usize, NoReflection> {
+    find_reflections(patterns, 0).into_iter().map(|reflection| reflection.map(|r| r.summary())).sum()
+}
+
+pub fn p2(patterns: &[Pattern]) -> Result<usize, NoReflection> {
+    find_reflections(patterns, 1).into_iter().map(|reflection| reflection.map(|r| r.summary())).sum()
+}
+
+// draws the pattern back as `.` and `#`, with the reflection line marked by `><` or `v^` and numbered
+// columns or rows, as in the task; the smudge is drawn as `O` instead of `.`, or `X` instead of `#`
+pub fn render(pattern: &Pattern, reflection: Option<Reflection>, smudge: Option<(usize, usize)>) -> String {
+    let grid = (0..pattern.height())
+        .map(|row| {
+            (0..pattern.width())
+                .map(|col| match (pattern.cell(row, col), smudge == Some((row, col))) {
+                    (true, false) => '#',
+                    (false, false) => '.',
+                    (true, true) => 'X',
+                    (false, true) => 'O',
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>();
+    // only the last digit fits into a single cell
+    let number = |idx: usize| char::from_digit((idx as u32 + 1) % 10, 10).unwrap();
+
+    let lines = match reflection {
+        None => grid,
+        Some(Horizontal(row)) => grid
+            .into_iter()
+            .enumerate()
+            .map(|(idx, cells)| {
+                let marker = if idx + 1 == row {
+                    'v'
+                } else if idx == row {
+                    '^'
+                } else {
+                    ' '
+                };
+                format!("{}{marker}{cells}{marker}{}", number(idx), number(idx))
+            })
+            .collect(),
+        Some(Vertical(col)) => {
+            let numbers = (0..pattern.width()).map(number).collect::<String>();
+            let markers = format!("{}><", " ".repeat(col - 1));
+
+            let mut lines = vec![numbers.clone(), markers.clone()];
+            lines.extend(grid);
+            lines.extend([markers, numbers]);
+            lines
+        }
+    };
+
+    lines.into_iter().map(|line| line.trim_end().to_string() + "\n").collect()
+}
+
+// renders the part 2 reflection with the smudge, or the part 1 reflection if there's no smudge
+pub fn render_unsmudged(pattern: &Pattern) -> String {
+    match find_unsmudged_symmetry(pattern) {
+        Some((reflection, smudge)) => render(pattern, Some(reflection), Some(smudge)),
+        None => render(pattern, find_symmetry(pattern, None), None),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Symmetry {
+    // mirror along the top-left to bottom-right diagonal
+    MainDiagonal,
+    // mirror along the top-right to bottom-left diagonal
+    AntiDiagonal,
+    // rotation by 180°
+    HalfTurn,
+    // rotation by 90°
+    QuarterTurn,
 }
 
-fn find_symmetry(
-    pattern: &Pattern,
-    prev_answer: (Option<usize>, Option<usize>),
-) -> Option<(Option<usize>, Option<usize>)> {
-    let mut row = 0;
-    while row < pattern.rows.len() - 1 {
-        let mirror_half_size = (row + 1).min(pattern.rows.len() - row - 1);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub top: usize,
+    pub left: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl Region {
+    fn contains(&self, other: &Region) -> bool {
+        self.top <= other.top
+            && self.left <= other.left
+            && other.top + other.height <= self.top + self.height
+            && other.left + other.width <= self.left + self.width
+    }
+}
+
+impl Pattern {
+    // rows and columns of a region, as bits starting from its top-left corner
+    fn region_row(&self, region: &Region, row: usize) -> Bits {
+        self.rows[region.top + row].slice(region.left, region.width)
+    }
+
+    fn region_col(&self, region: &Region, col: usize) -> Bits {
+        self.cols[region.left + col].slice(region.top, region.height)
+    }
+
+    // square regions of `size` in each possible position
+    fn squares(&self, size: usize) -> Vec<Region> {
+        let (height, width) = (self.rows.len(), self.cols.len());
+
+        (0..=(height - size))
+            .flat_map(|top| (0..=(width - size)).map(move |left| Region { top, left, height: size, width: size }))
+            .collect()
+    }
 
-        let mut reflected = true;
-        for offset in 0..mirror_half_size {
-            if pattern.rows[row - offset] != pattern.rows[row + offset + 1] {
-                reflected = false;
-                break;
+    fn has_symmetry(&self, symmetry: Symmetry, region: &Region) -> bool {
+        let size = region.height;
+
+        match symmetry {
+            // cell (r, c) is the same as (c, r), so row i is the same as column i
+            MainDiagonal => (0..size).all(|idx| self.region_row(region, idx) == self.region_col(region, idx)),
+            // cell (r, c) is the same as (size - c - 1, size - r - 1),
+            // so row i is the same as the reversed column (size - i - 1)
+            AntiDiagonal => (0..size)
+                .all(|idx| self.region_row(region, idx) == self.region_col(region, size - idx - 1).reversed(size)),
+            // cell (r, c) is the same as (height - r - 1, width - c - 1)
+            HalfTurn => (0..size).all(|idx| {
+                self.region_row(region, idx) == self.region_row(region, size - idx - 1).reversed(region.width)
+            }),
+            // cell (r, c) is the same as (size - c - 1, r), so row i is the same as the reversed column i
+            QuarterTurn => {
+                (0..size).all(|idx| self.region_row(region, idx) == self.region_col(region, idx).reversed(size))
             }
         }
+    }
+}
 
-        if reflected {
-            // task uses 1-indexes
-            let candidate = (Some(row + 1), None);
-            if candidate != prev_answer {
-                return Some(candidate);
+// diagonal mirror and rotational symmetries, with the regions they cover; diagonal mirrors and quarter turns
+// need a square, so they are checked on square regions from the largest size down to `min_size`, and only
+// the regions that aren't inside a bigger one with the same symmetry are reported. Half turns are checked
+// on the whole pattern. Single cells are symmetric in every way, so they're always left out, and small squares
+// are often symmetric by chance, which is what `min_size` is for.
+pub fn find_extra_symmetries(pattern: &Pattern, min_size: usize) -> Vec<(Symmetry, Region)> {
+    let mut symmetries: Vec<(Symmetry, Region)> = vec![];
+
+    for size in (min_size.max(2)..=pattern.rows.len().min(pattern.cols.len())).rev() {
+        for region in pattern.squares(size) {
+            for symmetry in [MainDiagonal, AntiDiagonal, QuarterTurn] {
+                let covered = symmetries.iter().any(|(other, bigger)| *other == symmetry && bigger.contains(&region));
+                if !covered && pattern.has_symmetry(symmetry, &region) {
+                    symmetries.push((symmetry, region));
+                }
             }
         }
-        row += 1;
     }
 
-    let mut col = 0;
-    while col < pattern.cols.len() - 1 {
-        let mirror_half_size = (col + 1).min(pattern.cols.len() - col - 1);
+    let whole = Region { top: 0, left: 0, height: pattern.rows.len(), width: pattern.cols.len() };
+    if whole.height * whole.width > 1 && pattern.has_symmetry(HalfTurn, &whole) {
+        symmetries.push((HalfTurn, whole));
+    }
 
-        let mut reflected = true;
-        for offset in 0..mirror_half_size {
-            if pattern.cols[col - offset] != pattern.cols[col + offset + 1] {
-                reflected = false;
-                break;
-            }
+    symmetries
+}
+
+// minimum number of cell flips to make each line a perfect reflection: `rows[i]` is for `Horizontal(i + 1)`,
+// and `cols[i]` is for `Vertical(i + 1)`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmudgeProfile {
+    pub rows: Vec<usize>,
+    pub cols: Vec<usize>,
+}
+
+// each mirrored pair of cells that differ needs exactly one flip, so it's just the number of defects
+pub fn smudge_profile(pattern: &Pattern) -> SmudgeProfile {
+    SmudgeProfile {
+        rows: (0..(pattern.height() - 1)).map(|row| mirror_defects(&pattern.rows, row, usize::MAX)).collect(),
+        cols: (0..(pattern.width() - 1)).map(|col| mirror_defects(&pattern.cols, col, usize::MAX)).collect(),
+    }
+}
+
+// the pattern with the cells spaced out and the profile written around it: column lines' values are
+// written vertically above the gaps between the columns, and row lines' values are to the right of the row
+// just above the line
+pub fn smudge_profile_view(pattern: &Pattern) -> String {
+    let profile = smudge_profile(pattern);
+    let col_values = profile.cols.iter().map(|flips| flips.to_string()).collect::<Vec<_>>();
+    let digits = col_values.iter().map(|value| value.len()).max().unwrap_or(0);
+
+    let mut view = String::new();
+    for digit in 0..digits {
+        let mut line = String::new();
+        for value in &col_values {
+            // values are right-aligned, so the shorter ones start a few lines later
+            let padding = digits - value.len();
+            line.push(' ');
+            line.push(if digit < padding { ' ' } else { value.as_bytes()[digit - padding] as char });
         }
+        view.push_str(line.trim_end());
+        view.push('\n');
+    }
 
-        if reflected {
-            // same as above, adjust for 1-indexed task
-            let candidate = (None, Some(col + 1));
-            if candidate != prev_answer {
-                return Some(candidate);
-            }
+    for row in 0..pattern.height() {
+        let cells = (0..pattern.width()).map(|col| if pattern.cell(row, col) { "#" } else { "." }).collect::<Vec<_>>();
+        view.push_str(&cells.join(" "));
+
+        if let Some(flips) = profile.rows.get(row) {
+            view.push_str(&format!("  {flips}"));
         }
-        col += 1;
+        view.push('\n');
     }
 
-    None
+    view
+}
+
+// pattern over any alphabet of single-byte symbols, e.g., several terrain types; patterns with at most two symbols
+// are stored as a `Pattern` to keep using the fast bitwise comparisons
+#[derive(Debug, Clone)]
+pub enum SymbolPattern {
+    Binary(Pattern),
+    Symbols { rows: Vec<Vec<u8>>, cols: Vec<Vec<u8>>, index: usize, line: usize },
+}
+
+impl SymbolPattern {
+    fn no_reflection(&self) -> NoReflection {
+        match self {
+            SymbolPattern::Binary(pattern) => NoReflection { index: pattern.index, line: pattern.line },
+            SymbolPattern::Symbols { index, line, .. } => NoReflection { index: *index, line: *line },
+        }
+    }
+}
+
+pub fn parse_symbols(input: &str) -> Vec<SymbolPattern> {
+    let mut patterns = vec![];
+
+    for (index, line, pattern) in blocks(input) {
+        let mut rows = pattern.split("\n").map(|row| row.as_bytes().to_vec()).collect::<Vec<_>>();
+        // missing cells at the ends of shorter rows are `.`, same as in `parse_pattern`
+        let width = rows.iter().map(|row| row.len()).max().unwrap();
+        for row in rows.iter_mut() {
+            row.resize(width, b'.');
+        }
+
+        let mut alphabet = rows.iter().flatten().copied().collect::<Vec<_>>();
+        alphabet.sort();
+        alphabet.dedup();
+
+        if alphabet.len() <= 2 {
+            // `#` stays `#` if it's there, so that the binary patterns look the same
+            let one = if alphabet.contains(&b'#') { b'#' } else { *alphabet.last().unwrap() };
+            let binary = rows
+                .iter()
+                .map(|row| row.iter().map(|&symbol| if symbol == one { '#' } else { '.' }).collect::<String>())
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            patterns.push(SymbolPattern::Binary(parse_pattern(&binary, index, line)));
+        } else {
+            let cols = (0..width).map(|col| rows.iter().map(|row| row[col]).collect()).collect();
+            patterns.push(SymbolPattern::Symbols { rows, cols, index, line });
+        }
+    }
+
+    patterns
 }
 
-fn symmetry_summary(symmetry: (Option<usize>, Option<usize>)) -> usize {
-    match symmetry {
-        (Some(row), None) => 100 * row,
-        (None, Some(col)) => col,
-        _ => unreachable!(),
+// replacing a symbol with any other one fixes a defect, same as flipping a bit in the binary case
+pub fn find_symbol_reflection_with_defects(pattern: &SymbolPattern, k: usize) -> Vec<Reflection> {
+    match pattern {
+        SymbolPattern::Binary(pattern) => find_reflection_with_defects(pattern, k),
+        SymbolPattern::Symbols { rows, cols, .. } => reflections_with_defects(rows, cols, k),
     }
 }
 
-pub fn p1(patterns: &Vec<Pattern>) -> usize {
-    patterns.iter().map(|p| symmetry_summary(find_symmetry(p, (None, None)).unwrap())).sum()
+// the first reflection with exactly `smudges` defects, as in `find_reflections`
+pub fn find_symbol_reflection(pattern: &SymbolPattern, smudges: usize) -> Result<Reflection, NoReflection> {
+    find_symbol_reflection_with_defects(pattern, smudges).into_iter().next().ok_or_else(|| pattern.no_reflection())
 }
 
-fn find_unsmudged_symmetry(pattern: &Pattern) -> Option<(Option<usize>, Option<usize>)> {
-    let prev_answer = find_symmetry(pattern, (None, None)).unwrap();
+// xorshift64*, enough to generate reproducible patterns without pulling in a dependency
+struct Rng(u64);
 
-    for smudge_row in 0..pattern.rows.len() {
-        for smudge_col in 0..pattern.cols.len() {
-            let mut unsmudged_pattern = pattern.clone();
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // xorshift gets stuck on 0, and close seeds should still give very different sequences
+        Rng(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1)
+    }
 
-            // we use bitwise xor to flip a corresponding bit located at (smudge_row, smudge_col)
-            let row_bit = pattern.cols.len() - smudge_col - 1;
-            let new_row_number = unsmudged_pattern.rows[smudge_row] ^ (1 << row_bit);
-            unsmudged_pattern.rows[smudge_row] = new_row_number;
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545F4914F6CDD1D)
+    }
 
-            let col_bit = pattern.rows.len() - smudge_row - 1;
-            let new_col_number = unsmudged_pattern.cols[smudge_col] ^ (1 << col_bit);
-            unsmudged_pattern.cols[smudge_col] = new_col_number;
+    fn below(&mut self, max: usize) -> usize {
+        (self.next() % max as u64) as usize
+    }
+}
 
-            match find_symmetry(&unsmudged_pattern, prev_answer) {
-                None => continue,
-                Some(new_symmetry) => {
-                    return Some(new_symmetry);
+// generates a random `height`x`width` pattern in the input format that has a perfect `reflection`, or,
+// with `smudge`, has it after fixing a single cell. Every other line needs at least 3 flips before adding the smudge,
+// so it can't reflect in either case. Returns `None` if no such pattern is found after a lot of attempts,
+// which happens for tiny sizes.
+pub fn generate_pattern(
+    seed: u64,
+    height: usize,
+    width: usize,
+    reflection: Reflection,
+    smudge: bool,
+) -> Option<String> {
+    let mut rng = Rng::new(seed);
+
+    for _attempt in 0..10000 {
+        let mut cells =
+            (0..height).map(|_| (0..width).map(|_| rng.next() & 1 == 1).collect::<Vec<_>>()).collect::<Vec<_>>();
+
+        // copy the cells before the line to the other side of it
+        match reflection {
+            Horizontal(line) => {
+                for row in line..height.min(2 * line) {
+                    cells[row] = cells[2 * line - row - 1].clone();
+                }
+            }
+            Vertical(line) => {
+                for row in cells.iter_mut() {
+                    for col in line..width.min(2 * line) {
+                        row[col] = row[2 * line - col - 1];
+                    }
                 }
             }
         }
+
+        let render_cells = |cells: &Vec<Vec<bool>>| {
+            cells
+                .iter()
+                .map(|row| row.iter().map(|&cell| if cell { '#' } else { '.' }).collect::<String>() + "\n")
+                .collect::<String>()
+        };
+
+        let pattern = &parse_input(&render_cells(&cells))[0];
+        let other_lines_reflect = (0..(height - 1))
+            .filter(|&row| Horizontal(row + 1) != reflection)
+            .any(|row| mirror_defects(&pattern.rows, row, 2) < 3)
+            || (0..(width - 1))
+                .filter(|&col| Vertical(col + 1) != reflection)
+                .any(|col| mirror_defects(&pattern.cols, col, 2) < 3);
+        if other_lines_reflect {
+            continue;
+        }
+
+        if smudge {
+            // the smudge has to be in the mirrored part to count
+            let (row, col) = match reflection {
+                Horizontal(line) => (line - 1 - rng.below(mirror_half_size(height, line - 1)), rng.below(width)),
+                Vertical(line) => (rng.below(height), line - 1 - rng.below(mirror_half_size(width, line - 1))),
+            };
+            cells[row][col] = !cells[row][col];
+        }
+
+        return Some(render_cells(&cells));
     }
 
     None
 }
 
-pub fn p2(patterns: &Vec<Pattern>) -> usize {
-    patterns.iter().map(|pattern| symmetry_summary(find_unsmudged_symmetry(pattern).unwrap())).sum()
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -161,21 +669,352 @@ mod tests {
     fn p1_test() {
         let test_patterns = parse_input(TEST_INPUT);
         assert_eq!(
-            test_patterns.iter().map(|p| find_symmetry(p, (None, None)).unwrap()).collect::<Vec<_>>(),
-            vec![(None, Some(5)), (Some(4), None)]
+            test_patterns.iter().map(|p| find_symmetry(p, None).unwrap()).collect::<Vec<_>>(),
+            vec![Vertical(5), Horizontal(4)]
         );
-        assert_eq!(p1(&test_patterns), 405);
+        assert_eq!(p1(&test_patterns), Ok(405));
 
         let patterns = parse_input(&fs::read_to_string("../inputs/d13").unwrap());
-        assert_eq!(p1(&patterns), 34993);
+        assert_eq!(p1(&patterns), Ok(34993));
     }
 
     #[test]
     fn p2_test() {
         let test_patterns = parse_input(TEST_INPUT);
-        assert_eq!(p2(&test_patterns), 400);
+        assert_eq!(p2(&test_patterns), Ok(400));
 
         let patterns = parse_input(&fs::read_to_string("../inputs/d13").unwrap());
-        assert_eq!(p2(&patterns), 29341);
+        assert_eq!(p2(&patterns), Ok(29341));
+    }
+
+    #[test]
+    fn wide_pattern_test() {
+        // 100x100 pattern that only reflects between columns 38 and 39
+        let size = 100;
+        let cell = |row: usize, col: usize| {
+            let col = if (38..=75).contains(&col) { 75 - col } else { col };
+            (row * 7919 + col * 104729 + row * col * 31) % 11 < 5
+        };
+
+        let input = (0..size)
+            .map(|row| (0..size).map(|col| if cell(row, col) { '#' } else { '.' }).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let patterns = parse_input(&input);
+        assert_eq!(patterns[0].rows.len(), size);
+        assert_eq!(patterns[0].cols.len(), size);
+        assert_eq!(patterns[0].rows[3].get(70), cell(3, 70));
+        assert_eq!(patterns[0].cols[70].get(3), cell(3, 70));
+
+        assert_eq!(find_symmetry(&patterns[0], None), Some(Vertical(38)));
+        assert_eq!(p1(&patterns), Ok(38));
+    }
+
+    #[test]
+    fn find_reflection_with_defects_test() {
+        let test_patterns = parse_input(TEST_INPUT);
+        assert_eq!(find_reflection_with_defects(&test_patterns[0], 0), vec![Vertical(5)]);
+        assert_eq!(find_reflection_with_defects(&test_patterns[1], 0), vec![Horizontal(4)]);
+        assert_eq!(find_reflection_with_defects(&test_patterns[0], 1), vec![Horizontal(3)]);
+        assert_eq!(find_reflection_with_defects(&test_patterns[1], 1), vec![Horizontal(1)]);
+        assert_eq!(find_reflection_with_defects(&test_patterns[0], 2), vec![Vertical(1)]);
+    }
+
+    #[test]
+    fn find_all_reflections_test() {
+        let test_patterns = parse_input(TEST_INPUT);
+        assert_eq!(find_all_reflections(&test_patterns[0]), vec![ReflectionLine { symmetry: Vertical(5), covered: 4 }]);
+
+        // symmetric both ways, and with three vertical reflections
+        let patterns = parse_input(
+            "#..##..#
+.##..##.
+.##..##.
+#..##..#
+",
+        );
+        assert_eq!(
+            find_all_reflections(&patterns[0]),
+            vec![
+                ReflectionLine { symmetry: Horizontal(2), covered: 2 },
+                ReflectionLine { symmetry: Vertical(2), covered: 2 },
+                ReflectionLine { symmetry: Vertical(4), covered: 4 },
+                ReflectionLine { symmetry: Vertical(6), covered: 2 },
+            ]
+        );
+        assert_eq!(find_symmetry(&patterns[0], None), Some(Horizontal(2)));
+    }
+
+    #[test]
+    fn no_reflection_test() {
+        let input = format!("\n{TEST_INPUT}\n#.#\n.#.\n\n{TEST_INPUT}");
+        let patterns = parse_input(&input);
+
+        let reflections = find_reflections(&patterns, 0);
+        assert_eq!(reflections[1], Ok(Horizontal(4)));
+        assert_eq!(reflections[2], Err(NoReflection { index: 2, line: 18 }));
+        assert_eq!(reflections[3], Ok(Vertical(5)));
+
+        let error = p1(&patterns).unwrap_err();
+        assert_eq!(error.to_string(), "pattern #2 starting at line 18 has no reflection");
+        assert_eq!(p2(&patterns), Err(NoReflection { index: 2, line: 18 }));
+    }
+
+    fn to_bits(binary: &str) -> Bits {
+        let mut bits = Bits::zeros(binary.len());
+        for (idx, ch) in binary.chars().enumerate() {
+            if ch == '1' {
+                bits.set(idx);
+            }
+        }
+        bits
+    }
+
+    #[test]
+    fn bits_test() {
+        let bits = to_bits(&"0110".repeat(50));
+        assert_eq!(bits.slice(1, 2), to_bits("11"));
+        assert_eq!(bits.slice(62, 70), to_bits(&"1001".repeat(18)[..70]));
+        assert_eq!(bits.slice(130, 3), to_bits("100"));
+        assert_eq!(bits.reversed(6), to_bits("100110"));
+        assert_eq!(bits.reversed(200), bits);
+    }
+
+    fn square_at(top: usize, left: usize, size: usize) -> Region {
+        Region { top, left, height: size, width: size }
+    }
+
+    #[test]
+    fn find_extra_symmetries_test() {
+        let patterns = parse_input(
+            "#..
+.##
+.#.
+
+.#..
+...#
+#...
+..#.
+
+.#..#
+#.##.
+#.##.
+
+#.#.
+#...
+..##
+",
+        );
+
+        let square = Region { top: 0, left: 0, height: 3, width: 3 };
+        assert_eq!(find_extra_symmetries(&patterns[0], 3), vec![(MainDiagonal, square)]);
+
+        let square = Region { top: 0, left: 0, height: 4, width: 4 };
+        assert_eq!(find_extra_symmetries(&patterns[1], 3), vec![(QuarterTurn, square), (HalfTurn, square)]);
+
+        // the pattern isn't square, but its middle and right 3x3 parts are mirrored along the diagonals
+        assert_eq!(
+            find_extra_symmetries(&patterns[2], 3),
+            vec![
+                (MainDiagonal, Region { top: 0, left: 1, height: 3, width: 3 }),
+                (AntiDiagonal, Region { top: 0, left: 2, height: 3, width: 3 })
+            ]
+        );
+
+        assert_eq!(find_extra_symmetries(&patterns[3], 3), vec![]);
+
+        // with smaller squares, the 2x2 corner of the first one is mirrored along the other diagonal too,
+        // while its other 2x2 squares mirrored along the main diagonal are inside the 3x3 one
+        assert_eq!(
+            find_extra_symmetries(&patterns[0], 0),
+            vec![(MainDiagonal, square_at(0, 0, 3)), (AntiDiagonal, square_at(0, 0, 2))]
+        );
+
+        // a symmetric square somewhere inside a bigger pattern
+        let patterns = parse_input(
+            "#.###....
+....####.
+.###....#
+..#.##...
+.#..#..#.
+..#..#.##
+.##.#..##
+
+#..#
+",
+        );
+        assert_eq!(find_extra_symmetries(&patterns[0], 3), vec![(MainDiagonal, square_at(2, 3, 3))]);
+
+        // no squares bigger than single cells, but it's the same turned around
+        assert_eq!(
+            find_extra_symmetries(&patterns[1], 0),
+            vec![(HalfTurn, Region { top: 0, left: 0, height: 1, width: 4 })]
+        );
+    }
+
+    #[test]
+    fn smudge_profile_test() {
+        let test_patterns = parse_input(TEST_INPUT);
+
+        let profile = smudge_profile(&test_patterns[0]);
+        assert_eq!(profile.rows, vec![5, 13, 1, 15, 10, 5]);
+        assert_eq!(profile.cols, vec![2, 11, 13, 16, 0, 11, 8, 7]);
+
+        // perfect and smudged reflections match the part 1 and part 2 answers
+        assert_eq!(smudge_profile(&test_patterns[1]).rows[3], 0);
+        assert_eq!(smudge_profile(&test_patterns[1]).rows[0], 1);
+
+        assert_eq!(
+            smudge_profile_view(&test_patterns[0]),
+            "   1 1 1   1
+ 2 1 3 6 0 1 8 7
+# . # # . . # # .  5
+. . # . # # . # .  13
+# # . . . . . . #  1
+# # . . . . . . #  15
+. . # . # # . # .  10
+. . # # . . # # .  5
+# . # . # # . # .
+"
+        );
+    }
+
+    #[test]
+    fn render_test() {
+        let test_patterns = parse_input(TEST_INPUT);
+
+        assert_eq!(
+            render(&test_patterns[0], find_symmetry(&test_patterns[0], None), None),
+            "123456789
+    ><
+#.##..##.
+..#.##.#.
+##......#
+##......#
+..#.##.#.
+..##..##.
+#.#.##.#.
+    ><
+123456789
+"
+        );
+        assert_eq!(render(&test_patterns[0], None, None), TEST_INPUT.split("\n\n").next().unwrap().to_string() + "\n");
+
+        // fixing either of the mirrored cells works, so in the second pattern we pick (0, 4),
+        // while the task's description picks (1, 4)
+        assert_eq!(find_unsmudged_symmetry(&test_patterns[0]), Some((Horizontal(3), (0, 0))));
+        assert_eq!(find_unsmudged_symmetry(&test_patterns[1]), Some((Horizontal(1), (0, 4))));
+        assert_eq!(
+            render_unsmudged(&test_patterns[1]),
+            "1v#...X#..#v1
+2^#....#..#^2
+3 ..##..### 3
+4 #####.##. 4
+5 #####.##. 5
+6 ..##..### 6
+7 #....#..# 7
+"
+        );
+    }
+
+    #[test]
+    fn generate_pattern_test() {
+        assert_eq!(generate_pattern(42, 7, 9, Vertical(5), true), generate_pattern(42, 7, 9, Vertical(5), true));
+        assert_ne!(generate_pattern(42, 7, 9, Vertical(5), true), generate_pattern(43, 7, 9, Vertical(5), true));
+
+        let mut rng = Rng::new(2023);
+        for seed in 0..200 {
+            let (height, width) = (5 + rng.below(15), 5 + rng.below(15));
+            let reflection = if rng.below(2) == 0 {
+                Horizontal(1 + rng.below(height - 1))
+            } else {
+                Vertical(1 + rng.below(width - 1))
+            };
+
+            let input = generate_pattern(seed, height, width, reflection, false).unwrap();
+            let patterns = parse_input(&input);
+            assert_eq!((patterns[0].height(), patterns[0].width()), (height, width));
+            assert_eq!(find_all_reflections(&patterns[0]).len(), 1);
+            assert_eq!(p1(&patterns), Ok(reflection.summary()));
+
+            let input = generate_pattern(seed, height, width, reflection, true).unwrap();
+            let patterns = parse_input(&input);
+            assert_eq!(p1(&patterns), Err(NoReflection { index: 0, line: 1 }));
+            assert_eq!(p2(&patterns), Ok(reflection.summary()));
+            assert_eq!(find_reflection_with_defects(&patterns[0], 1), vec![reflection]);
+        }
+
+        // in a 2x2 pattern, the other line only compares two pairs of cells, so it can't need 3 flips
+        assert_eq!(generate_pattern(1, 2, 2, Horizontal(1), false), None);
+    }
+
+    #[test]
+    fn symbols_test() {
+        let patterns = parse_symbols(
+            "^~~~~.
+~.^^.~
+~.^^.~
+
+#.
+.#
+
+abc
+bca
+",
+        );
+
+        assert!(matches!(patterns[0], SymbolPattern::Symbols { .. }));
+        assert_eq!(find_symbol_reflection_with_defects(&patterns[0], 0), vec![Horizontal(2)]);
+        // replacing `^` at (0, 0) with `.` makes it reflect between columns 3 and 4
+        assert_eq!(find_symbol_reflection_with_defects(&patterns[0], 1), vec![Vertical(3)]);
+
+        assert!(matches!(patterns[1], SymbolPattern::Binary(_)));
+        assert_eq!(find_symbol_reflection_with_defects(&patterns[1], 0), vec![]);
+        assert_eq!(find_symbol_reflection_with_defects(&patterns[1], 2), vec![Horizontal(1), Vertical(1)]);
+
+        let binary_patterns = parse_symbols(TEST_INPUT);
+        for (pattern, binary_pattern) in parse_input(TEST_INPUT).iter().zip(&binary_patterns) {
+            let SymbolPattern::Binary(binary_pattern) = binary_pattern else {
+                panic!("{binary_pattern:?} is not binary")
+            };
+            assert_eq!(binary_pattern.rows, pattern.rows);
+        }
+
+        assert_eq!(find_symbol_reflection_with_defects(&patterns[2], 0), vec![]);
+        assert_eq!(find_symbol_reflection_with_defects(&patterns[2], 2), vec![Vertical(1), Vertical(2)]);
+
+        // patterns without a reflection are reported the same way for any alphabet
+        assert_eq!(find_symbol_reflection(&patterns[0], 1), Ok(Vertical(3)));
+        assert_eq!(find_symbol_reflection(&patterns[1], 0), Err(NoReflection { index: 1, line: 5 }));
+        assert_eq!(find_symbol_reflection(&patterns[2], 0), Err(NoReflection { index: 2, line: 8 }));
+
+        // shorter rows are filled up with `.`
+        let ragged = parse_symbols("ab\nab.\nc\n");
+        let SymbolPattern::Symbols { rows, .. } = &ragged[0] else { panic!("{:?} is binary", ragged[0]) };
+        assert_eq!(rows, &vec![b"ab.".to_vec(), b"ab.".to_vec(), b"c..".to_vec()]);
+        assert_eq!(find_symbol_reflection(&ragged[0], 0), Ok(Horizontal(1)));
+    }
+
+    #[test]
+    fn parse_patterns_test() {
+        let mut patterns = parse_patterns(TEST_INPUT);
+        let first = patterns.next().unwrap();
+        assert_eq!((first.index, first.line, first.height(), first.width()), (0, 1, 7, 9));
+        assert!(first.cell(0, 0) && !first.cell(0, 1) && first.cell(6, 7));
+        assert_eq!(first.cols[0], to_bits("1011001"));
+        let second = patterns.next().unwrap();
+        assert_eq!((second.index, second.line), (1, 9));
+        assert!(patterns.next().is_none());
+
+        // a large generated input, parsed and solved one pattern at a time
+        let input = (0..200)
+            .map(|seed| generate_pattern(seed, 100, 120, Vertical(1 + seed as usize % 119), false).unwrap())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let start_time = std::time::Instant::now();
+        let summary =
+            parse_patterns(&input).map(|pattern| find_symmetry(&pattern, None).unwrap().summary()).sum::<usize>();
+        dbg!(std::time::Instant::now() - start_time);
+        assert_eq!(summary, (0..200).map(|seed| 1 + seed % 119).sum());
     }
 }
@@ -1,5 +1,6 @@
-use std::collections::hash_map::Entry;
-use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
 use std::hash::Hash;
 
 use Direction::*;
@@ -15,6 +16,17 @@ pub enum Direction {
 static ALL_DIRECTIONS: [Direction; 4] = [Left, Right, Up, Down];
 
 impl Direction {
+    // the opposite of `Display`
+    pub fn from_arrow(ch: char) -> Option<Direction> {
+        match ch {
+            '<' => Some(Left),
+            '>' => Some(Right),
+            '^' => Some(Up),
+            'v' => Some(Down),
+            _ => None,
+        }
+    }
+
     pub fn opposite(&self) -> Direction {
         match self {
             Left => Right,
@@ -33,15 +45,15 @@ impl Direction {
     }
 }
 
-impl ToString for Direction {
-    fn to_string(&self) -> String {
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let ch = match self {
             Left => "<",
             Right => ">",
             Up => "^",
             Down => "v",
         };
-        ch.to_string()
+        write!(f, "{ch}")
     }
 }
 
@@ -52,6 +64,10 @@ pub struct Pos {
 }
 
 impl Pos {
+    pub fn new(row: usize, col: usize) -> Pos {
+        Pos { row, col }
+    }
+
     pub fn walk(&self, direction: Direction) -> Option<Pos> {
         match direction {
             Left => {
@@ -74,12 +90,56 @@ impl Pos {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topology {
+    // walking off the edge isn't possible, as in the task
+    Bounded,
+    // walking off one edge enters the map again on the opposite side
+    Toroidal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terrain {
+    // entering a block costs its heat loss, as in the task
+    HeatLoss,
+    // the digits are elevations, and a move costs `flat` plus `uphill` or `downhill` for each level of difference
+    Elevation { flat: usize, uphill: usize, downhill: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CostModel {
+    pub terrain: Terrain,
+    // added every time the crucible changes its direction
+    pub turn_penalty: usize,
+    // added for every straight run, including the first one
+    pub run_penalty: usize,
+}
+
+impl CostModel {
+    pub fn heat_loss() -> CostModel {
+        CostModel { terrain: Terrain::HeatLoss, turn_penalty: 0, run_penalty: 0 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Map {
-    rows: Vec<Vec<usize>>,
+    // heat loss of each block, `None` for walls that can't be entered
+    rows: Vec<Vec<Option<usize>>>,
+    topology: Topology,
+    costs: CostModel,
+    // parsed from blocks separated by whitespace or commas, so heat losses can have several digits
+    separated: bool,
 }
 
 impl Map {
+    pub fn with_topology(self, topology: Topology) -> Map {
+        Map { topology, ..self }
+    }
+
+    pub fn with_costs(self, costs: CostModel) -> Map {
+        Map { costs, ..self }
+    }
+
     pub fn max_row(&self) -> usize {
         self.rows.len()
     }
@@ -88,57 +148,192 @@ impl Map {
         self.rows[0].len()
     }
 
+    pub fn is_open(&self, pos: &Pos) -> bool {
+        self.rows[pos.row][pos.col].is_some()
+    }
+
     pub fn heat_loss(&self, pos: &Pos) -> usize {
-        self.rows[pos.row][pos.col]
+        self.rows[pos.row][pos.col].expect("walls can't be entered")
     }
 
-    pub fn next_moves(&self, pos: &Pos, direction: &Direction, steps: usize, p2: bool) -> Vec<(Pos, Direction)> {
-        let allowed_directions =
-            if p2 { p2_allowed_directions(direction, steps) } else { p1_allowed_directions(direction, steps) };
+    // the neighbouring block in the direction, if there's any
+    pub fn step(&self, pos: &Pos, direction: Direction) -> Option<Pos> {
+        match self.topology {
+            Topology::Bounded => pos.walk(direction).filter(|pos| pos.row < self.max_row() && pos.col < self.max_col()),
+            Topology::Toroidal => {
+                let (rows, cols) = (self.max_row(), self.max_col());
+                let (row, col) = match direction {
+                    Left => (pos.row, (pos.col + cols - 1) % cols),
+                    Right => (pos.row, (pos.col + 1) % cols),
+                    Up => ((pos.row + rows - 1) % rows, pos.col),
+                    Down => ((pos.row + 1) % rows, pos.col),
+                };
+                Some(Pos { row, col })
+            }
+        }
+    }
+
+    // the fewest blocks between the two positions
+    pub fn distance(&self, a: &Pos, b: &Pos) -> usize {
+        let (rows, cols) = (a.row.abs_diff(b.row), a.col.abs_diff(b.col));
+        match self.topology {
+            Topology::Bounded => rows + cols,
+            Topology::Toroidal => rows.min(self.max_row() - rows) + cols.min(self.max_col() - cols),
+        }
+    }
+
+    // cost of moving to a neighbouring block without the penalties for turns and runs
+    pub fn terrain_cost(&self, from: &Pos, to: &Pos) -> usize {
+        match self.costs.terrain {
+            Terrain::HeatLoss => self.heat_loss(to),
+            Terrain::Elevation { flat, uphill, downhill } => {
+                let (from, to) = (self.heat_loss(from), self.heat_loss(to));
+                if to > from {
+                    flat + (to - from) * uphill
+                } else {
+                    flat + (from - to) * downhill
+                }
+            }
+        }
+    }
+
+    // the most a move to a neighbouring block can cost, saturating at usize::MAX
+    pub fn max_terrain_cost(&self) -> usize {
+        let heat_losses = self.rows.iter().flatten().flatten();
+        match self.costs.terrain {
+            Terrain::HeatLoss => heat_losses.max().copied().unwrap_or(0),
+            Terrain::Elevation { flat, uphill, downhill } => {
+                let (Some(lowest), Some(highest)) = (heat_losses.clone().min(), heat_losses.max()) else {
+                    return flat;
+                };
+                (highest - lowest).saturating_mul(uphill.max(downhill)).saturating_add(flat)
+            }
+        }
+    }
+
+    // the most a single move can cost, with the penalties
+    pub fn max_move_cost(&self) -> usize {
+        self.max_terrain_cost().saturating_add(self.costs.turn_penalty).saturating_add(self.costs.run_penalty)
+    }
+
+    // the least a move to a neighbouring block can cost
+    pub fn min_terrain_cost(&self) -> usize {
+        match self.costs.terrain {
+            Terrain::HeatLoss => self.rows.iter().flatten().flatten().min().copied().unwrap_or(0),
+            Terrain::Elevation { flat, .. } => flat,
+        }
+    }
 
-        allowed_directions
+    // moves to the neighbouring blocks allowed by the rules, with their cost
+    pub fn next_moves(
+        &self,
+        pos: &Pos,
+        direction: &Direction,
+        steps: usize,
+        rules: &CrucibleRules,
+    ) -> Vec<(Pos, Direction, usize)> {
+        rules
+            .allowed_directions(direction, steps)
             .into_iter()
-            .filter_map(|d| {
-                pos.walk(d).and_then(|new_pos| {
-                    if new_pos.row < self.max_row() && new_pos.col < self.max_col() {
-                        Some((new_pos, d))
-                    } else {
-                        None
-                    }
-                })
+            .filter_map(|d| self.step(pos, d).filter(|new_pos| self.is_open(new_pos)).map(|new_pos| (new_pos, d)))
+            .map(|(new_pos, d)| {
+                let mut cost = self.terrain_cost(pos, &new_pos);
+                // the crucible starts its first run without turning
+                if d != *direction || steps == 0 {
+                    cost += self.costs.run_penalty;
+                }
+                if d != *direction && steps > 0 {
+                    cost += self.costs.turn_penalty;
+                }
+                (new_pos, d, cost)
             })
             .collect()
     }
 }
 
-pub fn parse_input(input: &str) -> Map {
-    let mut rows = vec![];
+fn is_separator(ch: char) -> bool {
+    ch == ',' || ch.is_whitespace()
+}
 
-    for row in input.trim().split("\n") {
-        let row = row.chars().map(|ch| ch.to_digit(10).unwrap() as usize).collect();
-        rows.push(row);
+// either a digit per block as in the task, or blocks separated by whitespace or commas as in `parse_separated`;
+// whitespace around a row doesn't separate anything, so it doesn't switch a digit grid to the separated format
+pub fn parse_input(input: &str) -> Map {
+    if input.trim().lines().any(|line| line.trim().contains(is_separator)) {
+        return parse_separated(input);
     }
 
-    Map { rows }
+    let rows = input
+        .trim()
+        .lines()
+        .map(|row| {
+            row.trim()
+                .chars()
+                .map(|ch| if ch == '#' { None } else { Some(ch.to_digit(10).unwrap() as usize) })
+                .collect()
+        })
+        .collect();
+    new_map(rows, false)
 }
 
-fn p1_allowed_directions(direction: &Direction, steps: usize) -> Vec<Direction> {
-    if steps == 3 {
-        direction.turns().to_vec()
-    } else {
-        let turns = direction.turns();
-        vec![*direction, turns[0], turns[1]]
-    }
+// blocks separated by whitespace or commas, which allows bigger heat losses; `#` is a wall as in the task
+pub fn parse_separated(input: &str) -> Map {
+    let rows = input
+        .trim()
+        .lines()
+        .map(|row| {
+            row.split(is_separator)
+                .filter(|block| !block.is_empty())
+                .map(|block| if block == "#" { None } else { Some(block.parse().unwrap()) })
+                .collect()
+        })
+        .collect();
+    new_map(rows, true)
 }
 
-fn p2_allowed_directions(direction: &Direction, steps: usize) -> Vec<Direction> {
-    if steps == 10 {
-        direction.turns().to_vec()
-    } else if steps < 4 {
-        vec![*direction]
-    } else {
-        let turns = direction.turns();
-        vec![*direction, turns[0], turns[1]]
+fn new_map(rows: Vec<Vec<Option<usize>>>, separated: bool) -> Map {
+    assert!(rows.iter().all(|row| row.len() == rows[0].len()), "all rows have to be the same length");
+    Map { rows, topology: Topology::Bounded, costs: CostModel::heat_loss(), separated }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrucibleRules {
+    // blocks to move in a direction before the crucible can turn or stop at the target
+    pub min_straight: usize,
+    // blocks after which the crucible has to turn
+    pub max_straight: usize,
+    // whether the crucible can go back the way it came, with the same limits as for turning
+    pub can_reverse: bool,
+}
+
+impl CrucibleRules {
+    pub fn crucible() -> CrucibleRules {
+        CrucibleRules { min_straight: 1, max_straight: 3, can_reverse: false }
+    }
+
+    pub fn ultra_crucible() -> CrucibleRules {
+        CrucibleRules { min_straight: 4, max_straight: 10, can_reverse: false }
+    }
+
+    // `steps` is the number of blocks already moved in `direction`; 0 is only possible at the start
+    pub fn allowed_directions(&self, direction: &Direction, steps: usize) -> Vec<Direction> {
+        let mut directions = vec![];
+
+        if steps < self.max_straight {
+            directions.push(*direction);
+        }
+        if steps >= self.min_straight {
+            directions.extend(direction.turns());
+            if self.can_reverse {
+                directions.push(direction.opposite());
+            }
+        }
+
+        directions
+    }
+
+    // 0 steps means the crucible hasn't moved from the start yet, so it's already where it needs to be
+    pub fn can_stop(&self, steps: usize) -> bool {
+        steps == 0 || steps >= self.min_straight
     }
 }
 
@@ -150,82 +345,799 @@ struct State {
     steps: usize,
 }
 
-impl PartialOrd for State {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.heat_loss.partial_cmp(&other.heat_loss).map(|ordering| ordering.reverse())
+// maps (position, direction, steps) states to indexes in flat vectors, which are a lot faster than hash maps
+struct StateSpace {
+    cols: usize,
+    steps: usize,
+    len: usize,
+}
+
+impl StateSpace {
+    fn new(map: &Map, rules: &CrucibleRules) -> StateSpace {
+        // a straight run can't be longer than the map, so rules without a limit still get a small state space;
+        // on a torus it can go around, but once it has the minimum steps, going around again only makes it worse
+        let longest = match map.topology {
+            Topology::Bounded => map.max_row().max(map.max_col()),
+            Topology::Toroidal => rules.min_straight.saturating_add(map.max_row().max(map.max_col())),
+        };
+        let steps = rules.max_straight.min(longest) + 1;
+        StateSpace { cols: map.max_col(), steps, len: map.max_row() * map.max_col() * ALL_DIRECTIONS.len() * steps }
+    }
+
+    fn index(&self, pos: &Pos, direction: Direction, steps: usize) -> usize {
+        ((pos.row * self.cols + pos.col) * ALL_DIRECTIONS.len() + direction as usize) * self.steps + steps
+    }
+
+    fn state(&self, idx: usize) -> (Pos, Direction, usize) {
+        let (idx, steps) = (idx / self.steps, idx % self.steps);
+        let (idx, direction) = (idx / ALL_DIRECTIONS.len(), ALL_DIRECTIONS[idx % ALL_DIRECTIONS.len()]);
+        (Pos { row: idx / self.cols, col: idx % self.cols }, direction, steps)
+    }
+
+    // each block entered after the first state, with the direction it moved in
+    fn path(&self, states: &[usize]) -> Vec<(Pos, Direction)> {
+        states
+            .iter()
+            .skip(1)
+            .map(|&idx| {
+                let (pos, direction, _steps) = self.state(idx);
+                (pos, direction)
+            })
+            .collect()
     }
 }
 
-impl Ord for State {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Heuristic {
+    // plain Dijkstra
+    Zero,
+    // Manhattan distance to the target times the minimum heat loss of a block
+    Manhattan,
+    // exact heat loss to the target if the crucible could move freely, precomputed with a reverse search
+    Relaxed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unreachable {
+    pub start: Pos,
+    pub target: Pos,
+}
+
+impl fmt::Display for Unreachable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the crucible can't get from ({}, {}) to ({}, {})",
+            self.start.row, self.start.col, self.target.row, self.target.col
+        )
     }
 }
 
-pub fn dijkstra(map: &Map, p2: bool) -> usize {
-    let start = Pos { row: 0, col: 0 };
-    let target = Pos { row: map.max_row() - 1, col: map.max_col() - 1 };
-    // we can arrive to each block facing various directions and having made 0 to 3 steps in this direction
-    // those are possible states we need to examine, so we a key of (position, direction, walked_steps)
-    // instead of just position to figure out which states we have already considered
-    let mut visited: HashSet<(Pos, Direction, usize)> = HashSet::new();
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Search {
+    pub heat_loss: usize,
+    // each block the crucible enters, with the direction it moved in
+    pub path: Vec<(Pos, Direction)>,
+    // number of expanded (position, direction, steps) states
+    pub visited: usize,
+}
 
+// lower bounds of the heat loss from each block to the target, indexed by [row][col]
+fn lower_bounds(map: &Map, target: Pos, heuristic: Heuristic) -> Vec<Vec<usize>> {
+    match heuristic {
+        Heuristic::Zero => vec![vec![0; map.max_col()]; map.max_row()],
+        Heuristic::Manhattan => {
+            let min_cost = map.min_terrain_cost();
+            (0..map.max_row())
+                .map(|row| (0..map.max_col()).map(|col| map.distance(&Pos { row, col }, &target) * min_cost).collect())
+                .collect()
+        }
+        Heuristic::Relaxed => {
+            // Dijkstra from the target without the crucible rules; moving from a block to its neighbour
+            // costs the neighbour's heat loss, so going backwards we pay for the block we come from
+            let mut bounds = vec![vec![usize::MAX; map.max_col()]; map.max_row()];
+            bounds[target.row][target.col] = 0;
+
+            let mut queue = BinaryHeap::new();
+            queue.push(Reverse((0, target.row, target.col)));
+
+            while let Some(Reverse((bound, row, col))) = queue.pop() {
+                if bound > bounds[row][col] {
+                    continue;
+                }
+
+                let pos = Pos { row, col };
+                for direction in ALL_DIRECTIONS {
+                    if let Some(prev) = map.step(&pos, direction).filter(|prev| map.is_open(prev)) {
+                        let prev_bound = bound + map.terrain_cost(&prev, &pos);
+                        if prev_bound < bounds[prev.row][prev.col] {
+                            bounds[prev.row][prev.col] = prev_bound;
+                            queue.push(Reverse((prev_bound, prev.row, prev.col)));
+                        }
+                    }
+                }
+            }
+
+            bounds
+        }
+    }
+}
+
+// lower bounds of the heat loss to the target, for the search to add to the heat loss of each state
+enum Bounds {
+    // the same for all the states on a block, indexed by [row][col]
+    Blocks(Vec<Vec<usize>>),
+    // for each (position, direction, steps) state, indexed as in `StateSpace`
+    States(Vec<usize>),
+}
+
+impl Bounds {
+    fn get(&self, pos: &Pos, idx: usize) -> usize {
+        match self {
+            Bounds::Blocks(bounds) => bounds[pos.row][pos.col],
+            Bounds::States(bounds) => bounds[idx],
+        }
+    }
+
+    // the most a move can add to the estimate of a state
+    fn span(&self, map: &Map) -> usize {
+        match self {
+            // a move adds its cost to the estimate, and changes the lower bound by at most the terrain cost of moving
+            // back (with the relaxed heuristic) or the least terrain cost (with Manhattan distances)
+            Bounds::Blocks(_) => map.max_move_cost().saturating_add(map.max_terrain_cost()),
+            // the bound of the next state can be arbitrarily bigger, when it has to go a long way around
+            Bounds::States(_) => usize::MAX,
+        }
+    }
+}
+
+// best heat loss from each state to stopping at the target, which is the tightest lower bound for the searches
+// of Yen's algorithm: they only take some of the moves away, so they can't do better than that
+fn state_bounds(map: &Map, rules: &CrucibleRules, space: &StateSpace, target: Pos) -> Vec<usize> {
+    // Dijkstra from the target, going back over the moves, so we need the states each state can be entered from
+    let mut entries = vec![vec![]; space.len];
+    for idx in 0..space.len {
+        let (pos, direction, steps) = space.state(idx);
+        if !map.is_open(&pos) {
+            continue;
+        }
+        for (neighbour, new_direction, cost) in map.next_moves(&pos, &direction, steps, rules) {
+            let steps = if new_direction == direction { steps + 1 } else { 1 };
+            if steps < space.steps {
+                entries[space.index(&neighbour, new_direction, steps)].push((idx, cost));
+            }
+        }
+    }
+
+    let mut bounds = vec![usize::MAX; space.len];
     let mut queue = BinaryHeap::new();
-    queue.push(State { pos: start, heat_loss: 0, direction: Right, steps: 0 });
-    queue.push(State { pos: start, heat_loss: 0, direction: Down, steps: 0 });
+    for direction in ALL_DIRECTIONS {
+        for steps in (0..space.steps).filter(|&steps| rules.can_stop(steps)) {
+            let idx = space.index(&target, direction, steps);
+            bounds[idx] = 0;
+            queue.push(Reverse((0, idx)));
+        }
+    }
+
+    while let Some(Reverse((bound, idx))) = queue.pop() {
+        if bound > bounds[idx] {
+            continue;
+        }
+        for &(prev, cost) in &entries[idx] {
+            let prev_bound = bound + cost;
+            if prev_bound < bounds[prev] {
+                bounds[prev] = prev_bound;
+                queue.push(Reverse((prev_bound, prev)));
+            }
+        }
+    }
+
+    bounds
+}
+
+// returns the minimal heat loss and the path with it: each block the crucible enters, with the direction it moved in
+pub fn dijkstra(map: &Map, rules: &CrucibleRules) -> Result<(usize, Vec<(Pos, Direction)>), Unreachable> {
+    let Search { heat_loss, path, visited: _ } = search(map, rules, Heuristic::Zero)?;
+    Ok((heat_loss, path))
+}
+
+// A* search from the top-left to the bottom-right corner, as in the task
+pub fn search(map: &Map, rules: &CrucibleRules, heuristic: Heuristic) -> Result<Search, Unreachable> {
+    let target = Pos { row: map.max_row() - 1, col: map.max_col() - 1 };
+    search_path(map, rules, heuristic, Pos { row: 0, col: 0 }, &[Right, Down], target)
+}
 
-    // in part 2, only state with >= 4 steps in a direction can count for reaching target,
+// A* search; with `Heuristic::Zero` it's the same as Dijkstra. Both heuristics are consistent,
+// so the first time we take the target from the queue, we've found the best path to it.
+pub fn search_path(
+    map: &Map,
+    rules: &CrucibleRules,
+    heuristic: Heuristic,
+    start: Pos,
+    directions: &[Direction],
+    target: Pos,
+) -> Result<Search, Unreachable> {
+    let bounds = Bounds::Blocks(lower_bounds(map, target, heuristic));
+
+    let mut found = None;
+    let exploration = explore(
+        map,
+        rules,
+        &bounds,
+        &start_states(start, directions),
+        |_from, _to| true,
+        |idx, state| {
+            if state.pos == target && rules.can_stop(state.steps) {
+                found = Some((idx, state.heat_loss));
+            }
+            found.is_some()
+        },
+    );
+
+    let (idx, heat_loss) = found.ok_or(Unreachable { start, target })?;
+    Ok(Search { heat_loss, path: exploration.path(idx), visited: exploration.visited_count })
+}
+
+// best heat loss for each of the `targets`, or `None` if the crucible can't stop there
+pub fn best_costs(
+    map: &Map,
+    rules: &CrucibleRules,
+    start: Pos,
+    directions: &[Direction],
+    targets: &[Pos],
+) -> Vec<Option<usize>> {
+    let bounds = Bounds::Blocks(lower_bounds(map, start, Heuristic::Zero));
+    let mut costs = vec![None; targets.len()];
+    let mut remaining = targets.len();
+
+    // with Dijkstra, the first time we stop at a target is the best one, and we can stop once we found all of them
+    explore(
+        map,
+        rules,
+        &bounds,
+        &start_states(start, directions),
+        |_from, _to| true,
+        |_idx, state| {
+            if rules.can_stop(state.steps) {
+                for (target, cost) in targets.iter().zip(costs.iter_mut()) {
+                    if *target == state.pos && cost.is_none() {
+                        *cost = Some(state.heat_loss);
+                        remaining -= 1;
+                    }
+                }
+            }
+            remaining == 0
+        },
+    );
+
+    costs
+}
+
+// best heat loss from `start` for every block of the map, indexed by [row][col]
+pub fn cost_field(map: &Map, rules: &CrucibleRules, start: Pos, directions: &[Direction]) -> Vec<Vec<Option<usize>>> {
+    let bounds = Bounds::Blocks(lower_bounds(map, start, Heuristic::Zero));
+    let mut field = vec![vec![None; map.max_col()]; map.max_row()];
+
+    explore(
+        map,
+        rules,
+        &bounds,
+        &start_states(start, directions),
+        |_from, _to| true,
+        |_idx, state| {
+            let cost = &mut field[state.pos.row][state.pos.col];
+            if rules.can_stop(state.steps) && cost.is_none() {
+                *cost = Some(state.heat_loss);
+            }
+            false
+        },
+    );
+
+    field
+}
+
+// the part of the state space explored by a search
+struct Exploration {
+    space: StateSpace,
+    visited: Vec<bool>,
+    // with a minimum number of straight steps, only some of the states can count for reaching target,
     // so we need to remember the number of steps and direction to differentiate between different states
-    let mut heat_losses = HashMap::new();
-    for direction in ALL_DIRECTIONS {
-        heat_losses.insert((start, direction, 0), 0);
+    heat_losses: Vec<usize>,
+    // index of the state we came from to each state with its current best heat loss
+    predecessors: Vec<usize>,
+    // states changed by the last search, so that the next one only has to reset those
+    touched: Vec<usize>,
+    visited_count: usize,
+}
+
+impl Exploration {
+    fn new(map: &Map, rules: &CrucibleRules) -> Exploration {
+        // we can arrive to each block facing various directions and having made a different number of steps
+        // in this direction; those are possible states we need to examine, so we a key of (position, direction, walked_steps)
+        // instead of just position to figure out which states we have already considered
+        let space = StateSpace::new(map, rules);
+        let len = space.len;
+        Exploration {
+            space,
+            visited: vec![false; len],
+            heat_losses: vec![usize::MAX; len],
+            predecessors: vec![usize::MAX; len],
+            touched: vec![],
+            visited_count: 0,
+        }
+    }
+
+    // indexes of the states from the start state to the one with `idx`
+    fn states(&self, idx: usize) -> Vec<usize> {
+        let mut states = vec![idx];
+        while self.predecessors[*states.last().unwrap()] != usize::MAX {
+            states.push(self.predecessors[*states.last().unwrap()]);
+        }
+        states.reverse();
+        states
+    }
+
+    fn path(&self, idx: usize) -> Vec<(Pos, Direction)> {
+        self.space.path(&self.states(idx))
     }
 
-    while let Some(State { pos, heat_loss, direction, steps }) = queue.pop() {
-        for (neighbour, new_direction) in map.next_moves(&pos, &direction, steps + 1, p2) {
-            let steps = if new_direction == direction { steps + 1 } else { 0 };
+    fn reset(&mut self) {
+        for idx in self.touched.drain(..) {
+            self.visited[idx] = false;
+            self.heat_losses[idx] = usize::MAX;
+            self.predecessors[idx] = usize::MAX;
+        }
+        self.visited_count = 0;
+    }
+
+    // expands the states in the order of their heat loss plus the lower bound from `bounds`, starting with `seeds`
+    // and only taking the moves `can_enter` allows between two state indexes; it calls `on_visit` with each state
+    // and its index, until it returns true or there are no states left
+    fn explore(
+        &mut self,
+        map: &Map,
+        rules: &CrucibleRules,
+        bounds: &Bounds,
+        seeds: &[State],
+        can_enter: impl Fn(usize, usize) -> bool,
+        mut on_visit: impl FnMut(usize, &State) -> bool,
+    ) {
+        self.reset();
+        let Exploration { space, visited, heat_losses, predecessors, touched, visited_count } = self;
+
+        let mut queue = Queue::new(bounds.span(map));
+
+        // states without a way to the target have a lower bound of usize::MAX, so we never go there
+        for seed in seeds {
+            let idx = space.index(&seed.pos, seed.direction, seed.steps);
+            let bound = bounds.get(&seed.pos, idx);
+            if bound != usize::MAX {
+                heat_losses[idx] = seed.heat_loss;
+                touched.push(idx);
+                queue.push(seed.heat_loss + bound, idx);
+            }
+        }
+
+        while let Some(idx) = queue.pop() {
+            // the same state could've been pushed again with a better heat loss, and already expanded;
+            // the better one always comes first, so the heat loss of the state is its best one
+            if visited[idx] {
+                continue;
+            }
+            let (pos, direction, steps) = space.state(idx);
+            let state = State { pos, heat_loss: heat_losses[idx], direction, steps };
+            let heat_loss = state.heat_loss;
+            visited[idx] = true;
+            *visited_count += 1;
+
+            if on_visit(idx, &state) {
+                break;
+            }
 
-            if !visited.contains(&(neighbour, new_direction, steps)) {
-                let new_heat_loss = heat_loss + map.heat_loss(&neighbour);
-                let new_state = State { pos: neighbour, heat_loss: new_heat_loss, direction: new_direction, steps };
+            for (neighbour, new_direction, cost) in map.next_moves(&pos, &direction, steps, rules) {
+                let steps = if new_direction == direction { steps + 1 } else { 1 };
+                // only possible going around a torus, and never better than stopping the run earlier
+                if steps >= space.steps {
+                    continue;
+                }
+                let neighbour_idx = space.index(&neighbour, new_direction, steps);
 
                 // check if we found a better solution for (pos, direction, steps) and if so
-                // update the heat_losses map and push this new state in the queue
-                match heat_losses.entry((neighbour, new_direction, steps)) {
-                    Entry::Occupied(mut entry) => {
-                        if new_heat_loss < *entry.get() {
-                            entry.insert(new_heat_loss);
-                            queue.push(new_state);
-                        }
+                // update the heat losses and push this new state in the queue
+                let new_heat_loss = heat_loss + cost;
+                let bound = bounds.get(&neighbour, neighbour_idx);
+                if !visited[neighbour_idx]
+                    && new_heat_loss < heat_losses[neighbour_idx]
+                    && bound != usize::MAX
+                    && can_enter(idx, neighbour_idx)
+                {
+                    if heat_losses[neighbour_idx] == usize::MAX {
+                        touched.push(neighbour_idx);
                     }
-                    Entry::Vacant(entry) => {
-                        entry.insert(new_heat_loss);
-                        queue.push(new_state);
+                    heat_losses[neighbour_idx] = new_heat_loss;
+                    predecessors[neighbour_idx] = idx;
+
+                    queue.push(new_heat_loss + bound, neighbour_idx);
+                }
+            }
+        }
+    }
+}
+
+fn start_states(start: Pos, directions: &[Direction]) -> Vec<State> {
+    directions.iter().map(|&direction| State { pos: start, heat_loss: 0, direction, steps: 0 }).collect()
+}
+
+// a single search with fresh buffers
+fn explore(
+    map: &Map,
+    rules: &CrucibleRules,
+    bounds: &Bounds,
+    seeds: &[State],
+    can_enter: impl Fn(usize, usize) -> bool,
+    on_visit: impl FnMut(usize, &State) -> bool,
+) -> Exploration {
+    let mut exploration = Exploration::new(map, rules);
+    exploration.explore(map, rules, bounds, seeds, can_enter, on_visit);
+    exploration
+}
+
+// the most buckets we keep for Dial's algorithm before switching to a heap
+const MAX_BUCKETS: usize = 1 << 16;
+
+// priority queue of state indexes by their estimates
+enum Queue {
+    // Dial's algorithm: with small integer costs, instead of a heap we keep a bucket of states for each estimate,
+    // and go through the buckets in order. The queued estimates are never more than the number of buckets apart,
+    // so the buckets are reused in a ring.
+    Buckets { buckets: Vec<Vec<usize>>, current: usize, len: usize },
+    Heap(BinaryHeap<Reverse<(usize, usize)>>),
+}
+
+impl Queue {
+    // `span` is the most the estimates of the queued states can differ by
+    fn new(span: usize) -> Queue {
+        if span < MAX_BUCKETS {
+            Queue::Buckets { buckets: vec![vec![]; span + 1], current: usize::MAX, len: 0 }
+        } else {
+            Queue::Heap(BinaryHeap::new())
+        }
+    }
+
+    fn push(&mut self, estimate: usize, idx: usize) {
+        match self {
+            Queue::Buckets { buckets, current, len } => {
+                // only seeds can come before the current estimate, which all come before any other states
+                *current = (*current).min(estimate);
+                debug_assert!(estimate - *current < buckets.len());
+                let bucket = estimate % buckets.len();
+                buckets[bucket].push(idx);
+                *len += 1;
+            }
+            Queue::Heap(heap) => heap.push(Reverse((estimate, idx))),
+        }
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        match self {
+            Queue::Buckets { buckets, current, len } => {
+                if *len == 0 {
+                    return None;
+                }
+                // the estimates never decrease with consistent heuristics
+                loop {
+                    let bucket = *current % buckets.len();
+                    if let Some(idx) = buckets[bucket].pop() {
+                        *len -= 1;
+                        return Some(idx);
                     }
-                };
+                    *current += 1;
+                }
             }
+            Queue::Heap(heap) => heap.pop().map(|Reverse((_estimate, idx))| idx),
         }
+    }
+}
+
+// a loopless path through the state space, with the heat loss up to each of its states
+#[derive(Debug, Clone)]
+struct Route {
+    states: Vec<usize>,
+    heat_losses: Vec<usize>,
+    // the moves of the states after the start one, to tell the paths apart
+    path: Vec<(Pos, Direction)>,
+}
+
+impl Route {
+    fn new(space: &StateSpace, states: Vec<usize>, heat_losses: Vec<usize>) -> Route {
+        let path = space.path(&states);
+        Route { states, heat_losses, path }
+    }
+}
+
+// the `k` paths with the lowest heat loss from the top-left to the bottom-right corner, best first, as from `dijkstra`;
+// the paths never come back to the same (position, direction, steps) state, but they can cross the same block
+//
+// Yen's algorithm: each new path follows one of the paths found so far up to some state (the root), and then
+// takes the best way to the target that doesn't go back to the root, and doesn't continue as any found path did
+pub fn k_shortest_paths(map: &Map, rules: &CrucibleRules, k: usize) -> Vec<(usize, Vec<(Pos, Direction)>)> {
+    let target = Pos { row: map.max_row() - 1, col: map.max_col() - 1 };
+    let starts = start_states(Pos { row: 0, col: 0 }, &[Right, Down]);
+
+    // all the spur searches share the buffers over the state space
+    let space = StateSpace::new(map, rules);
+    let bounds = Bounds::States(state_bounds(map, rules, &space, target));
+    let blocked = vec![false; space.len];
+    let mut spurs = Spurs { exploration: Exploration::new(map, rules), blocked, map, rules, bounds, target };
+
+    let mut found: Vec<Route> = vec![];
+    let mut candidates: Vec<Route> = vec![];
 
-        visited.insert((pos, direction, steps));
+    if let Some((states, heat_losses)) = spurs.route(&starts, &[], &[], true) {
+        found.push(Route::new(&space, states, heat_losses));
     }
 
-    ALL_DIRECTIONS
+    while found.len() < k && !found.is_empty() {
+        let last = found.last().unwrap().clone();
+
+        // the root is the first `len` states of the last path; with an empty root, we can take another start,
+        // and with the whole path as the root, we can go on past the target and come back to stop there later
+        for len in 0..=last.states.len() {
+            let root = &last.states[..len];
+            let followers = found.iter().filter(|route| route.states.len() >= len && route.states[..len] == *root);
+
+            let spur = if len == 0 {
+                let used: Vec<_> = followers.map(|route| route.states[0]).collect();
+                let seeds: Vec<_> = starts
+                    .iter()
+                    .filter(|seed| !used.contains(&space.index(&seed.pos, seed.direction, seed.steps)))
+                    .cloned()
+                    .collect();
+                spurs.route(&seeds, &[], &[], true)
+            } else {
+                let (pos, direction, steps) = space.state(root[len - 1]);
+                let seed = State { pos, heat_loss: last.heat_losses[len - 1], direction, steps };
+                let mut ends_here = false;
+                let mut banned = vec![];
+                for route in followers {
+                    match route.states.get(len) {
+                        Some(&next) => banned.push(next),
+                        None => ends_here = true,
+                    }
+                }
+                spurs.route(&[seed], &root[..len - 1], &banned, !ends_here)
+            };
+
+            if let Some((states, heat_losses)) = spur {
+                let keep = len.saturating_sub(1);
+                let route = Route::new(
+                    &space,
+                    [&last.states[..keep], &states[..]].concat(),
+                    [&last.heat_losses[..keep], &heat_losses[..]].concat(),
+                );
+                // different start directions can lead to the same moves, which are the same path for us
+                if found.iter().chain(&candidates).all(|other| other.path != route.path) {
+                    candidates.push(route);
+                }
+            }
+        }
+
+        let Some(best) = (0..candidates.len()).min_by_key(|&i| candidates[i].heat_losses.last()) else {
+            break;
+        };
+        found.push(candidates.remove(best));
+    }
+
+    found.into_iter().map(|route| (*route.heat_losses.last().unwrap(), route.path)).collect()
+}
+
+// searches for the spur routes of Yen's algorithm
+struct Spurs<'a> {
+    exploration: Exploration,
+    // the root states, which the spur route can't go back to
+    blocked: Vec<bool>,
+    map: &'a Map,
+    rules: &'a CrucibleRules,
+    bounds: Bounds,
+    target: Pos,
+}
+
+impl Spurs<'_> {
+    // the states and heat losses of the best route from one of the `seeds` to the target, which doesn't go through
+    // the `blocked` states, nor to the `banned` states right from a seed; it can only end at a seed if `can_end_at_seed`
+    fn route(
+        &mut self,
+        seeds: &[State],
+        blocked: &[usize],
+        banned: &[usize],
+        can_end_at_seed: bool,
+    ) -> Option<(Vec<usize>, Vec<usize>)> {
+        let Spurs { exploration, blocked: is_blocked, map, rules, bounds, target } = self;
+        let seed_idxs: Vec<_> =
+            seeds.iter().map(|seed| exploration.space.index(&seed.pos, seed.direction, seed.steps)).collect();
+        for &idx in blocked {
+            is_blocked[idx] = true;
+        }
+
+        let mut found = None;
+        exploration.explore(
+            map,
+            rules,
+            bounds,
+            seeds,
+            |from, to| !is_blocked[to] && (!banned.contains(&to) || !seed_idxs.contains(&from)),
+            |idx, state| {
+                if state.pos == *target && rules.can_stop(state.steps) && (can_end_at_seed || !seed_idxs.contains(&idx))
+                {
+                    found = Some(idx);
+                }
+                found.is_some()
+            },
+        );
+
+        for &idx in blocked {
+            is_blocked[idx] = false;
+        }
+
+        let states = exploration.states(found?);
+        let heat_losses = states.iter().map(|&idx| exploration.heat_losses[idx]).collect();
+        Some((states, heat_losses))
+    }
+}
+
+// draws the path on the map with arrows in the direction of each move, as in the task
+pub fn render_path(map: &Map, path: &[(Pos, Direction)]) -> String {
+    let mut cells = map
+        .rows
         .iter()
-        .flat_map(|direction| {
-            let min_steps = if p2 { 3 } else { 0 };
-            (min_steps..=10).into_iter().flat_map(|steps| heat_losses.get(&(target, *direction, steps)))
+        .map(|row| {
+            row.iter()
+                .map(|heat_loss| heat_loss.map_or("#".to_string(), |heat_loss| heat_loss.to_string()))
+                .collect::<Vec<_>>()
         })
-        .min()
-        .map(|x| *x)
-        .unwrap()
+        .collect::<Vec<_>>();
+    let width = cells.iter().flatten().map(|cell| cell.len()).max().unwrap_or(0);
+    for (pos, direction) in path {
+        cells[pos.row][pos.col] = direction.to_string();
+    }
+
+    if !map.separated {
+        return cells.into_iter().map(|row| row.concat() + "\n").collect();
+    }
+
+    // heat losses can have several digits, so the cells are spaced out and aligned to the widest one
+    cells
+        .into_iter()
+        .map(|row| row.iter().map(|cell| format!("{cell:>width$}")).collect::<Vec<_>>().join(" ") + "\n")
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    // the arrow isn't one of `<>^v`
+    UnknownArrow { index: usize, ch: char },
+    // the block isn't next to the previous one
+    NotAdjacent { index: usize, pos: Pos },
+    // the move goes off the map or into a wall
+    Blocked { index: usize, direction: Direction },
+    // the crucible went back the way it came
+    Reversed { index: usize, direction: Direction },
+    // the crucible turned before it went the minimum number of blocks straight
+    EarlyTurn { index: usize, direction: Direction },
+    // the crucible went straight for more than the maximum number of blocks
+    TooStraight { index: usize, direction: Direction },
+    // the crucible didn't go the minimum number of blocks straight before stopping
+    EarlyStop { steps: usize },
+    // the path doesn't end at the target
+    WrongEnd { pos: Pos },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::UnknownArrow { index, ch } => write!(f, "move #{index}: unknown arrow {ch:?}"),
+            Violation::NotAdjacent { index, pos } => {
+                write!(f, "move #{index}: ({}, {}) isn't next to the previous block", pos.row, pos.col)
+            }
+            Violation::Blocked { index, direction } => write!(f, "move #{index}: can't move {direction}"),
+            Violation::Reversed { index, direction } => write!(f, "move #{index}: can't reverse to {direction}"),
+            Violation::EarlyTurn { index, direction } => write!(f, "move #{index}: can't turn to {direction} yet"),
+            Violation::TooStraight { index, direction } => {
+                write!(f, "move #{index}: can't go {direction} straight any longer")
+            }
+            Violation::EarlyStop { steps } => write!(f, "can't stop after {steps} blocks straight"),
+            Violation::WrongEnd { pos } => {
+                write!(f, "the path ends at ({}, {}) instead of the target", pos.row, pos.col)
+            }
+        }
+    }
+}
+
+// checks that the moves take the crucible from the top-left to the bottom-right corner by the rules,
+// and returns the heat loss of the path
+pub fn validate_moves(map: &Map, rules: &CrucibleRules, moves: &[Direction]) -> Result<usize, Violation> {
+    let target = Pos { row: map.max_row() - 1, col: map.max_col() - 1 };
+    let mut pos = Pos { row: 0, col: 0 };
+    // the crucible can start facing right or down, as in `search`; we pick the one which allows the first move
+    let mut direction = match moves.first() {
+        Some(&first) => [first, Right, Down]
+            .into_iter()
+            .find(|&d| (d == Right || d == Down) && rules.allowed_directions(&d, 0).contains(&first))
+            .unwrap_or(Right),
+        None => Right,
+    };
+    let mut steps = 0;
+    let mut heat_loss = 0;
+
+    for (index, &new_direction) in moves.iter().enumerate() {
+        let next_moves = map.next_moves(&pos, &direction, steps, rules);
+        let Some(&(next, _, cost)) = next_moves.iter().find(|(_, d, _)| *d == new_direction) else {
+            // a move off the map or into a wall is never possible, whatever the rules say about it
+            let violation = if map.step(&pos, new_direction).filter(|next| map.is_open(next)).is_none() {
+                Violation::Blocked { index, direction: new_direction }
+            } else if new_direction == direction {
+                Violation::TooStraight { index, direction: new_direction }
+            } else if new_direction == direction.opposite() && !rules.can_reverse {
+                Violation::Reversed { index, direction: new_direction }
+            } else if steps < rules.min_straight {
+                Violation::EarlyTurn { index, direction: new_direction }
+            } else {
+                Violation::Blocked { index, direction: new_direction }
+            };
+            return Err(violation);
+        };
+
+        steps = if new_direction == direction { steps + 1 } else { 1 };
+        direction = new_direction;
+        pos = next;
+        heat_loss += cost;
+    }
+
+    if pos != target {
+        return Err(Violation::WrongEnd { pos });
+    }
+    if !rules.can_stop(steps) {
+        return Err(Violation::EarlyStop { steps });
+    }
+    Ok(heat_loss)
+}
+
+// the moves as arrows like `>>>v>>^`, as in the task; whitespace is skipped
+pub fn validate_arrows(map: &Map, rules: &CrucibleRules, arrows: &str) -> Result<usize, Violation> {
+    let moves = arrows
+        .chars()
+        .filter(|ch| !ch.is_whitespace())
+        .enumerate()
+        .map(|(index, ch)| Direction::from_arrow(ch).ok_or(Violation::UnknownArrow { index, ch }))
+        .collect::<Result<Vec<_>, _>>()?;
+    validate_moves(map, rules, &moves)
+}
+
+// the blocks the crucible enters, as in the paths from `dijkstra`; the start block can be included or left out
+pub fn validate_positions(map: &Map, rules: &CrucibleRules, positions: &[Pos]) -> Result<usize, Violation> {
+    let start = Pos { row: 0, col: 0 };
+    // a block which isn't next to the previous one is reported at its index in `positions`, with the start block
+    let skipped = usize::from(positions.first() == Some(&start));
+
+    let mut moves = vec![];
+    let mut prev = start;
+    for (index, &pos) in positions.iter().enumerate().skip(skipped) {
+        let direction = ALL_DIRECTIONS
+            .into_iter()
+            .find(|&d| map.step(&prev, d) == Some(pos))
+            .ok_or(Violation::NotAdjacent { index, pos })?;
+        moves.push(direction);
+        prev = pos;
+    }
+    validate_moves(map, rules, &moves)
 }
 
-pub fn p1(map: &Map) -> usize {
-    dijkstra(map, false)
+pub fn p1(map: &Map) -> Result<usize, Unreachable> {
+    dijkstra(map, &CrucibleRules::crucible()).map(|(heat_loss, _path)| heat_loss)
 }
 
-pub fn p2(map: &Map) -> usize {
-    dijkstra(map, true)
+pub fn p2(map: &Map) -> Result<usize, Unreachable> {
+    dijkstra(map, &CrucibleRules::ultra_crucible()).map(|(heat_loss, _path)| heat_loss)
 }
 
 #[cfg(test)]
@@ -259,25 +1171,419 @@ mod tests {
     #[test]
     fn p1_test() {
         let test_map = parse_input(TEST_INPUT);
-        assert_eq!(p1(&test_map), 102);
+        assert_eq!(p1(&test_map), Ok(102));
 
         let map = parse_input(&fs::read_to_string("../inputs/d17").unwrap());
         let start_time = std::time::Instant::now();
-        assert_eq!(p1(&map), 886);
+        assert_eq!(p1(&map), Ok(886));
         dbg!(std::time::Instant::now() - start_time);
     }
 
     #[test]
     fn p2_test() {
         let test_map = parse_input(TEST_INPUT);
-        assert_eq!(p2(&test_map), 94);
+        assert_eq!(p2(&test_map), Ok(94));
 
         let test_map2 = parse_input(TEST_INPUT2);
-        assert_eq!(p2(&test_map2), 71);
+        assert_eq!(p2(&test_map2), Ok(71));
 
         let map = parse_input(&fs::read_to_string("../inputs/d17").unwrap());
         let start_time = std::time::Instant::now();
-        assert_eq!(p2(&map), 1055);
+        assert_eq!(p2(&map), Ok(1055));
         dbg!(std::time::Instant::now() - start_time);
+
+        for heuristic in [Heuristic::Zero, Heuristic::Manhattan, Heuristic::Relaxed] {
+            let start_time = std::time::Instant::now();
+            let search = search(&map, &CrucibleRules::ultra_crucible(), heuristic).unwrap();
+            assert_eq!(search.heat_loss, 1055);
+            dbg!(heuristic, search.visited, std::time::Instant::now() - start_time);
+        }
+    }
+
+    #[test]
+    fn crucible_rules_test() {
+        let test_map = parse_input(TEST_INPUT);
+
+        // has to turn after every block, so it zigzags along the diagonal
+        let zigzag = CrucibleRules { min_straight: 0, max_straight: 1, can_reverse: false };
+        assert_eq!(dijkstra(&test_map, &zigzag).unwrap().0, 133);
+
+        // no limits at all is just the shortest path
+        let unlimited = CrucibleRules { min_straight: 0, max_straight: usize::MAX, can_reverse: true };
+        assert_eq!(dijkstra(&test_map, &unlimited).unwrap().0, 78);
+
+        // the only ways are along the edges of the map
+        let long = CrucibleRules { min_straight: 7, max_straight: 20, can_reverse: false };
+        assert_eq!(dijkstra(&test_map, &long).unwrap().0, 79);
+
+        // same as part 2 when it's written out
+        assert_eq!(
+            dijkstra(&test_map, &CrucibleRules { min_straight: 4, max_straight: 10, can_reverse: false }).unwrap().0,
+            94
+        );
+    }
+
+    #[test]
+    fn first_run_test() {
+        // the start block doesn't count as a step, so the first run is as long as the others: the crucible
+        // can go 3 blocks right at once, and the ultra crucible can't turn after only 3 blocks
+        let map = parse_input("1111\n9991\n");
+        let (heat_loss, path) = dijkstra(&map, &CrucibleRules::crucible()).unwrap();
+        assert_eq!(heat_loss, 4);
+        assert_eq!(render_path(&map, &path), "1>>>\n999v\n");
+
+        let map = parse_input("11119999\n99919999\n99919999\n99919999\n99919999\n99911111\n");
+        let (heat_loss, path) = dijkstra(&map, &CrucibleRules::ultra_crucible()).unwrap();
+        assert_eq!(heat_loss, 68);
+        // turning down after the first 3 blocks would've cost only 12
+        assert_eq!(
+            render_path(&map, &path),
+            "11119999
+v9919999
+v9919999
+v9919999
+v9919999
+v>>>>>>>
+"
+        );
+    }
+
+    #[test]
+    fn path_test() {
+        let test_map = parse_input(TEST_INPUT);
+        let (heat_loss, path) = dijkstra(&test_map, &CrucibleRules::crucible()).unwrap();
+        assert_eq!(heat_loss, 102);
+        assert_eq!(path.iter().map(|(pos, _direction)| test_map.heat_loss(pos)).sum::<usize>(), 102);
+        assert_eq!(path.last().unwrap().0, Pos { row: 12, col: 12 });
+
+        let test_map2 = parse_input(TEST_INPUT2);
+        let (heat_loss, path) = dijkstra(&test_map2, &CrucibleRules::ultra_crucible()).unwrap();
+        assert_eq!(heat_loss, 71);
+        assert_eq!(
+            render_path(&test_map2, &path),
+            "1>>>>>>>1111
+9999999v9991
+9999999v9991
+9999999v9991
+9999999v>>>>
+"
+        );
+    }
+
+    #[test]
+    fn a_star_test() {
+        for (map, rules) in [
+            (parse_input(TEST_INPUT), CrucibleRules::crucible()),
+            (parse_input(TEST_INPUT), CrucibleRules::ultra_crucible()),
+            (parse_input(TEST_INPUT2), CrucibleRules::ultra_crucible()),
+        ] {
+            let dijkstra = search(&map, &rules, Heuristic::Zero).unwrap();
+            let manhattan = search(&map, &rules, Heuristic::Manhattan).unwrap();
+            let relaxed = search(&map, &rules, Heuristic::Relaxed).unwrap();
+
+            assert_eq!(manhattan.heat_loss, dijkstra.heat_loss);
+            assert_eq!(relaxed.heat_loss, dijkstra.heat_loss);
+            assert!(manhattan.visited <= dijkstra.visited);
+            assert!(relaxed.visited <= dijkstra.visited);
+        }
+    }
+
+    #[test]
+    fn best_costs_test() {
+        let test_map = parse_input(TEST_INPUT);
+        let rules = CrucibleRules::crucible();
+        let corner = Pos::new(12, 12);
+
+        assert_eq!(best_costs(&test_map, &rules, Pos::new(0, 0), &[Right, Down], &[corner]), vec![Some(102)]);
+
+        // going back, we pay for the top-left block instead of the bottom-right one
+        let back = search_path(&test_map, &rules, Heuristic::Relaxed, corner, &[Left, Up], Pos::new(0, 0)).unwrap();
+        assert_eq!(back.heat_loss, 101);
+        assert_eq!(back.path.last().map(|(pos, _)| *pos), Some(Pos::new(0, 0)));
+
+        let field = cost_field(&test_map, &rules, Pos::new(0, 0), &[Right, Down]);
+        assert_eq!(field[0][0], Some(0));
+        assert_eq!(field[0][1], Some(4));
+        assert_eq!(field[1][0], Some(3));
+        assert_eq!(field[12][12], Some(102));
+
+        let targets = [Pos::new(0, 0), Pos::new(0, 1), corner, Pos::new(5, 7)];
+        let costs = best_costs(&test_map, &rules, Pos::new(0, 0), &[Right, Down], &targets);
+        assert_eq!(costs, targets.iter().map(|pos| field[pos.row][pos.col]).collect::<Vec<_>>());
+
+        // starting in the middle, only going up; the block right above can't be a stop after one step,
+        // so we have to come back to it in a loop
+        let rules = CrucibleRules::ultra_crucible();
+        let costs = best_costs(&test_map, &rules, Pos::new(6, 6), &[Up], &[Pos::new(2, 6), corner, Pos::new(5, 6)]);
+        assert_eq!(costs, vec![Some(8 + 7 + 5 + 5), Some(93), Some(88)]);
+    }
+
+    #[test]
+    fn walls_test() {
+        let snake = parse_input("11111\n####1\n11111\n1####\n11111\n");
+        let unlimited = CrucibleRules { min_straight: 0, max_straight: usize::MAX, can_reverse: true };
+        assert_eq!(dijkstra(&snake, &unlimited).map(|(heat_loss, _path)| heat_loss), Ok(16));
+        assert_eq!(
+            render_path(&snake, &dijkstra(&snake, &unlimited).unwrap().1),
+            "1>>>>\n####v\n<<<<v\nv####\nv>>>>\n"
+        );
+
+        // the crucible can't go 4 blocks straight, and the ultra crucible can't turn after 2
+        let unreachable = Unreachable { start: Pos::new(0, 0), target: Pos::new(4, 4) };
+        assert_eq!(p1(&snake), Err(unreachable.clone()));
+        assert_eq!(p2(&snake), Err(unreachable.clone()));
+        assert_eq!(search(&snake, &CrucibleRules::crucible(), Heuristic::Relaxed), Err(unreachable.clone()));
+        assert_eq!(unreachable.to_string(), "the crucible can't get from (0, 0) to (4, 4)");
+
+        // the edges are too short for 7 blocks straight
+        let long = CrucibleRules { min_straight: 7, max_straight: 20, can_reverse: false };
+        assert!(dijkstra(&parse_input(TEST_INPUT2), &long).is_err());
+
+        // the target is walled off
+        let walled = parse_input("111\n1##\n1#1\n");
+        assert!(p1(&walled).is_err());
+        assert!(search(&walled, &unlimited, Heuristic::Manhattan).is_err());
+        assert!(search(&walled, &unlimited, Heuristic::Relaxed).is_err());
+    }
+
+    #[test]
+    fn huge_costs_test() {
+        // the queue mustn't grow with the heat loss
+        let map = parse_input("1,10000000000\n1,1\n");
+        assert_eq!(p1(&map), Ok(2));
+        let map = parse_input("1,10000000000\n#,1\n");
+        assert_eq!(p1(&map), Ok(10_000_000_001));
+        for heuristic in [Heuristic::Manhattan, Heuristic::Relaxed] {
+            assert_eq!(search(&map, &CrucibleRules::crucible(), heuristic).unwrap().heat_loss, 10_000_000_001);
+        }
+
+        let turns = CostModel { turn_penalty: 1_000_000_000_000, ..CostModel::heat_loss() };
+        let test_map = parse_input(TEST_INPUT).with_costs(turns);
+        assert_eq!(p1(&test_map), Ok(7_000_000_000_131));
+        assert_eq!(
+            search(&test_map, &CrucibleRules::crucible(), Heuristic::Relaxed).unwrap().heat_loss,
+            7_000_000_000_131
+        );
+    }
+
+    #[test]
+    fn separated_input_test() {
+        let map = parse_input("12,3,#\n1,#,7\n100,5,2\n");
+        assert_eq!(map, parse_input("12 3  #\n1\t# 7\n100, 5, 2\n"));
+        assert_eq!(map, parse_separated("12,3,#\n1,#,7\n100,5,2\n"));
+
+        // trailing whitespace on a digit grid doesn't make the digits one block each
+        assert_eq!(parse_input("241 \n321\n325\n"), parse_input("241\n321\n325\n"));
+        assert_eq!(parse_input(" 2#1\t\n321\n"), parse_input("2#1\n321\n"));
+        assert_eq!(parse_separated("241\n321\n").rows, vec![vec![Some(241)], vec![Some(321)]]);
+        assert_eq!(p1(&map), Ok(108));
+        assert_eq!(
+            render_path(&map, &dijkstra(&map, &CrucibleRules::crucible()).unwrap().1),
+            " 12   3   #
+  v   #   7
+  v   >   >
+"
+        );
+        assert_eq!(render_path(&parse_input("1 23\n4 5\n"), &[]), " 1 23\n 4  5\n");
+    }
+
+    #[test]
+    fn toroidal_test() {
+        let test_map = parse_input(TEST_INPUT).with_topology(Topology::Toroidal);
+        // down, then left to the other side and up to the corner
+        let (heat_loss, path) = dijkstra(&test_map, &CrucibleRules::crucible()).unwrap();
+        assert_eq!(heat_loss, 12);
+        assert_eq!(
+            path,
+            vec![(Pos::new(1, 0), Down), (Pos::new(1, 12), Left), (Pos::new(0, 12), Up), (Pos::new(12, 12), Up)]
+        );
+        assert_eq!(p2(&test_map), Ok(69));
+
+        let test_map2 = parse_input(TEST_INPUT2);
+        let long = CrucibleRules { min_straight: 7, max_straight: 20, can_reverse: false };
+        assert!(dijkstra(&test_map2, &long).is_err());
+        assert_eq!(p2(&test_map2.clone().with_topology(Topology::Toroidal)), Ok(50));
+        assert_eq!(dijkstra(&test_map2.with_topology(Topology::Toroidal), &long).unwrap().0, 20);
+
+        // the ultra crucible has to go around the whole map at least once
+        let small = parse_input("199\n999\n991\n").with_topology(Topology::Toroidal);
+        let (heat_loss, path) = dijkstra(&small, &CrucibleRules::ultra_crucible()).unwrap();
+        assert_eq!(heat_loss, 57);
+        assert_eq!(path.iter().map(|(pos, _direction)| small.heat_loss(pos)).sum::<usize>(), heat_loss);
+
+        for heuristic in [Heuristic::Manhattan, Heuristic::Relaxed] {
+            assert_eq!(search(&test_map, &CrucibleRules::ultra_crucible(), heuristic).unwrap().heat_loss, 69);
+        }
+        assert_eq!(test_map.distance(&Pos::new(0, 0), &Pos::new(12, 12)), 2);
+    }
+
+    #[test]
+    fn cost_model_test() {
+        let test_map = parse_input(TEST_INPUT);
+        assert_eq!(test_map.clone().with_costs(CostModel::heat_loss()), test_map);
+
+        let turns = CostModel { turn_penalty: 10, ..CostModel::heat_loss() };
+        assert_eq!(p1(&test_map.clone().with_costs(turns)), Ok(201));
+        assert_eq!(p2(&test_map.clone().with_costs(turns)), Ok(124));
+
+        // each run after the first one comes with a turn
+        let runs = CostModel { run_penalty: 5, ..CostModel::heat_loss() };
+        assert_eq!(p1(&test_map.clone().with_costs(runs)), Ok(164));
+        let both = CostModel { turn_penalty: 5, run_penalty: 5, ..CostModel::heat_loss() };
+        assert_eq!(p1(&test_map.clone().with_costs(both)), Ok(201 + 5));
+
+        let climbing =
+            CostModel { terrain: Terrain::Elevation { flat: 1, uphill: 3, downhill: 0 }, ..CostModel::heat_loss() };
+        assert_eq!(p1(&test_map.clone().with_costs(climbing)), Ok(51));
+        let hills = CostModel {
+            terrain: Terrain::Elevation { flat: 0, uphill: 1, downhill: 1 },
+            turn_penalty: 2,
+            run_penalty: 0,
+        };
+        assert_eq!(p2(&test_map.clone().with_costs(hills)), Ok(25));
+
+        // the heuristics only count the terrain, so they still find the best path
+        for costs in [turns, runs, climbing, hills] {
+            let map = test_map.clone().with_costs(costs);
+            let dijkstra = search(&map, &CrucibleRules::crucible(), Heuristic::Zero).unwrap();
+            for heuristic in [Heuristic::Manhattan, Heuristic::Relaxed] {
+                assert_eq!(search(&map, &CrucibleRules::crucible(), heuristic).unwrap().heat_loss, dijkstra.heat_loss);
+            }
+        }
+    }
+
+    #[test]
+    fn k_shortest_paths_test() {
+        let test_map = parse_input(TEST_INPUT);
+        let rules = CrucibleRules::crucible();
+        let paths = k_shortest_paths(&test_map, &rules, 8);
+        assert_eq!(paths.len(), 8);
+        assert_eq!(paths[0].0, dijkstra(&test_map, &rules).unwrap().0);
+        assert_eq!(
+            paths.iter().map(|(heat_loss, _path)| *heat_loss).collect::<Vec<_>>(),
+            vec![102, 102, 102, 102, 103, 103, 103, 103]
+        );
+        for (heat_loss, path) in &paths {
+            assert_eq!(path.iter().map(|(pos, _direction)| test_map.heat_loss(pos)).sum::<usize>(), *heat_loss);
+        }
+        for i in 1..paths.len() {
+            assert!(paths[..i].iter().all(|(_heat_loss, path)| *path != paths[i].1));
+        }
+
+        // same heat losses as going through all the paths
+        assert_eq!(
+            k_shortest_paths(&parse_input("241\n321\n325\n"), &rules, 8)
+                .iter()
+                .map(|(heat_loss, _path)| *heat_loss)
+                .collect::<Vec<_>>(),
+            vec![11, 11, 12, 12, 13, 13, 15, 16]
+        );
+        assert_eq!(
+            k_shortest_paths(&parse_input("2413\n3215\n3255\n3446\n"), &rules, 8)
+                .iter()
+                .map(|(heat_loss, _path)| *heat_loss)
+                .collect::<Vec<_>>(),
+            vec![21, 21, 21, 22, 22, 22, 22, 22]
+        );
+
+        // along either of the edges, or around in a loop that crosses its own beginning
+        let long = CrucibleRules { min_straight: 7, max_straight: 20, can_reverse: false };
+        let paths = k_shortest_paths(&test_map, &long, 3);
+        assert_eq!(paths.iter().map(|(heat_loss, _path)| *heat_loss).collect::<Vec<_>>(), vec![79, 87, 210]);
+        assert_eq!(
+            render_path(&test_map, &paths[2].1),
+            "2^>>>>>>>>>>>
+3^154535v562v
+3^552456v425v
+3^465858v545v
+4^466578v753v
+1^385987v845v
+4^578769v776v
+3<<<<<<<v965v
+465496798688v
+456467998645v
+122468686556v
+254654888773v
+432267465553v
+"
+        );
+        assert!(k_shortest_paths(&parse_input(TEST_INPUT2), &long, 3).is_empty());
+
+        // the second path goes on past the target, and comes back to stop there
+        let column = parse_input("8\n8\n3\n9\n");
+        let reversing = CrucibleRules { min_straight: 2, max_straight: 3, can_reverse: true };
+        let paths = k_shortest_paths(&column, &reversing, 6);
+        assert_eq!(paths.iter().map(|(heat_loss, _path)| *heat_loss).collect::<Vec<_>>(), vec![20, 43]);
+        assert_eq!(paths[1].1.iter().map(|(_pos, direction)| direction.to_string()).collect::<String>(), "vvv^^vv");
+    }
+
+    #[test]
+    fn validate_test() {
+        let test_map = parse_input(TEST_INPUT);
+        let (rules, ultra) = (CrucibleRules::crucible(), CrucibleRules::ultra_crucible());
+        let (heat_loss, path) = dijkstra(&test_map, &rules).unwrap();
+        let arrows = path.iter().map(|(_pos, direction)| direction.to_string()).collect::<String>();
+        let positions = path.iter().map(|(pos, _direction)| *pos).collect::<Vec<_>>();
+        assert_eq!(validate_arrows(&test_map, &rules, &arrows), Ok(heat_loss));
+        assert_eq!(validate_positions(&test_map, &rules, &positions), Ok(heat_loss));
+        assert_eq!(validate_positions(&test_map, &rules, &[&[Pos::new(0, 0)], &positions[..]].concat()), Ok(heat_loss));
+        assert_eq!(validate_moves(&test_map, &ultra, &[]), Err(Violation::WrongEnd { pos: Pos::new(0, 0) }));
+
+        let test_map2 = parse_input(TEST_INPUT2);
+        assert_eq!(validate_arrows(&test_map2, &ultra, ">>>>>>> vvvv >>>>"), Ok(71));
+        assert_eq!(
+            validate_arrows(&test_map2, &ultra, ">>>>>>>>>>>vvvv"),
+            Err(Violation::TooStraight { index: 10, direction: Right })
+        );
+        assert_eq!(
+            validate_arrows(&test_map2, &rules, ">>>>>>>vvvv>>>>"),
+            Err(Violation::TooStraight { index: 3, direction: Right })
+        );
+        assert_eq!(
+            validate_arrows(&test_map2, &ultra, ">>>v"),
+            Err(Violation::EarlyTurn { index: 3, direction: Down })
+        );
+        assert_eq!(
+            validate_arrows(&test_map2, &ultra, ">>>><<<<"),
+            Err(Violation::Reversed { index: 4, direction: Left })
+        );
+        assert_eq!(validate_arrows(&test_map2, &ultra, "vvvvv"), Err(Violation::Blocked { index: 4, direction: Down }));
+        assert_eq!(validate_arrows(&test_map2, &ultra, "^"), Err(Violation::Blocked { index: 0, direction: Up }));
+        assert_eq!(validate_arrows(&test_map2, &rules, "<"), Err(Violation::Blocked { index: 0, direction: Left }));
+        assert_eq!(validate_arrows(&test_map2, &ultra, ">v"), Err(Violation::EarlyTurn { index: 1, direction: Down }));
+        assert_eq!(
+            validate_arrows(&test_map2, &ultra, ">>>>>>>>>>vvvv"),
+            Err(Violation::WrongEnd { pos: Pos::new(4, 10) })
+        );
+        assert_eq!(
+            validate_arrows(&test_map2, &ultra, ">>>>>>>vvvv>>>>x"),
+            Err(Violation::UnknownArrow { index: 15, ch: 'x' })
+        );
+        assert_eq!(
+            validate_arrows(&test_map2, &rules, "vvv>>>v>>>>>>>>"),
+            Err(Violation::TooStraight { index: 10, direction: Right })
+        );
+
+        // the crucible can stop right after a turn, but the ultra crucible has to go 4 blocks straight first
+        let short = parse_input("11111\n11111\n");
+        assert_eq!(
+            validate_arrows(&short, &rules, ">>>>v"),
+            Err(Violation::TooStraight { index: 3, direction: Right })
+        );
+        assert_eq!(validate_arrows(&short, &rules, ">>>v>"), Ok(5));
+        assert_eq!(validate_arrows(&short, &ultra, ">>>>v"), Err(Violation::EarlyStop { steps: 1 }));
+
+        assert_eq!(
+            validate_positions(&test_map, &rules, &[Pos::new(0, 1), Pos::new(1, 2)]),
+            Err(Violation::NotAdjacent { index: 1, pos: Pos::new(1, 2) })
+        );
+        assert_eq!(
+            validate_positions(&test_map, &rules, &[Pos::new(0, 0), Pos::new(0, 1), Pos::new(1, 2)]),
+            Err(Violation::NotAdjacent { index: 2, pos: Pos::new(1, 2) })
+        );
+        let walls = parse_input("1#1\n111\n");
+        assert_eq!(validate_arrows(&walls, &rules, ">"), Err(Violation::Blocked { index: 0, direction: Right }));
+        assert_eq!(validate_arrows(&walls, &rules, "v>>"), Ok(3));
+        assert_eq!(Violation::Reversed { index: 4, direction: Left }.to_string(), "move #4: can't reverse to <");
     }
 }